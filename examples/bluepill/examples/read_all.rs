@@ -27,9 +27,7 @@
 
 // The part that matters:
 extern crate analog_multiplexer;
-use analog_multiplexer::{DummyPin, Multiplexer};
-// This is just a convenient container for keeping track of channel data and pretty-printing it:
-mod channels; // Look at channels/mod.rs if you're curious how it works
+use analog_multiplexer::{AnalogMultiplexer, Eh0Pin, Multiplexer, NoEnable, Snapshot};
 
 extern crate panic_halt;
 use cortex_m;
@@ -46,15 +44,20 @@ use stm32f1xx_hal::pac::ADC1;
 use stm32f1xx_hal::prelude::*;
 
 // Define which pins go to where on your analog multiplexer (for RTIC's `Resources`)
-type S0 = PB12<Output<PushPull>>; // These just make things easier to read/reason about
-type S1 = PB13<Output<PushPull>>; // aka "very expressive"
-type S2 = PB14<Output<PushPull>>;
-type S3 = PB15<Output<PushPull>>; // You can comment this out if using 8-channel (74HC4051)
-type EN = DummyPin; // If EN is connected to GND to keep it always enabled
-// type EN = PB5<Output<PushPull>>; // If you want to enable/disable the multiplexer on-the-fly
+// NOTE: stm32f1xx_hal 0.7 uses embedded-hal 0.2 so its pins get wrapped in `Eh0Pin`
+type S0 = Eh0Pin<PB12<Output<PushPull>>>; // These just make things easier to read/reason about
+type S1 = Eh0Pin<PB13<Output<PushPull>>>; // aka "very expressive"
+type S2 = Eh0Pin<PB14<Output<PushPull>>>;
+type S3 = Eh0Pin<PB15<Output<PushPull>>>; // You can comment this out if using 8-channel (74HC4051)
+type EN = NoEnable; // If EN is connected to GND to keep it always enabled
+// type EN = Eh0Pin<PB5<Output<PushPull>>>; // If you want to enable/disable the multiplexer on-the-fly
 // You can swap which line is commented below to use an 8-channel instead of 16:
-type Multiplex = Multiplexer<(S0, S1, S2, S3, EN)>; // If using 16-channel (74HC4067)
-// type Multiplex = Multiplexer<(S0, S1, S2, EN)>; // If using 8-channel (74HC4051)
+type Pins = (S0, S1, S2, S3, EN); // If using 16-channel (74HC4067)
+// type Pins = (S0, S1, S2, EN); // If using 8-channel (74HC4051)
+// The multiplexer owns the ADC and the analog pin connected to Z so it can read channels itself:
+type Multiplex = AnalogMultiplexer<Pins, adc::Adc<ADC1>, PB0<Analog>>;
+// Holds the value of every channel (and pretty-prints them).  Use Snapshot<8> for a 74HC4051:
+type ChannelValues = Snapshot<16>;
 // NOTE: If you swapped above you'll also need to swap the `let pins...` line below
 
 const PERIOD: u32 = 10_000_000; // Update state (and blink LED 1/2) every 100ms
@@ -67,9 +70,7 @@ const APP: () = {
     struct Resources {
         led: PC13<Output<PushPull>>,
         multiplexer: Multiplex, // If we didn't use the type aliases above this would be very messy
-        ch_states: channels::ChannelValues,
-        adc1: adc::Adc<ADC1>,
-        analog_pin: PB0<Analog>,
+        ch_states: ChannelValues,
     }
 
     // This is needed for probe-rs to work with RTIC at present (20200919).
@@ -136,7 +137,7 @@ const APP: () = {
             .pb15
             .into_push_pull_output_with_state(&mut gpiob.crh, State::Low);
 
-        let en = DummyPin; // Use the DummyPin if you have it always enabled (e.g. connected to GND)
+        let en = NoEnable; // Use NoEnable if you have it always enabled (e.g. connected to GND)
         // If you want to be able to enable/disable the multiplexer on-the-fly:
         // let en = gpiob
         //     .pb5
@@ -144,13 +145,15 @@ const APP: () = {
 
         // ** ANALOG MULTIPLEXER STUFF **
         // Setup the Multiplexer with our configured pins (swap comments below for 8 channel)
-        let pins = (s0, s1, s2, s3, en); // For 16-channel (74HC4067)
-                                         // let pins = (s0,s1,s2,en); // For 8-channel (74HC4051)
-        let mut multiplexer = Multiplexer::new(pins);
-        multiplexer.enable(); // Just an example (it gets enabled when you instantiate it)
+        let pins = (Eh0Pin(s0), Eh0Pin(s1), Eh0Pin(s2), Eh0Pin(s3), en); // For 16-channel (74HC4067)
+                                                                         // let pins = (Eh0Pin(s0),Eh0Pin(s1),Eh0Pin(s2),en); // For 8-channel (74HC4051)
+        let mut multiplexer = Multiplexer::new(pins).unwrap();
+        multiplexer.enable().unwrap(); // Just an example (it gets enabled when you instantiate it)
+        // Hand it the ADC and the analog pin so it can read channels for us:
+        let multiplexer = multiplexer.with_adc(adc1, analog_pin);
 
         // Keep track of channel states/values (for pretty printing)
-        let ch_states: channels::ChannelValues = Default::default();
+        let ch_states: ChannelValues = Default::default();
 
         // Schedule the reading/blinking task
         cx.schedule.readall(cx.start + PERIOD.cycles()).unwrap();
@@ -159,29 +162,25 @@ const APP: () = {
             led: led,
             multiplexer: multiplexer,
             ch_states: ch_states,
-            analog_pin: analog_pin, // NOTE: Wish we didn't need BOTH
-            adc1: adc1,             // the pin and the ADC to read it
         }
     }
 
-    #[task(resources = [led, multiplexer, ch_states, analog_pin, adc1], schedule = [readall])]
+    #[task(resources = [led, multiplexer, ch_states], schedule = [readall])]
     fn readall(cx: readall::Context) {
         // Use the safe local `static mut` of RTIC
         static mut LED_STATE: bool = false; // RTIC's blink scheduler boilerplate
                                             // These are just here to keep the code nice and concise:
         let multiplexer = cx.resources.multiplexer;
         let ch_states = cx.resources.ch_states;
-        let adc1 = cx.resources.adc1;
         let led = cx.resources.led;
 
         // ** ANALOG MULTIPLEXER STUFF **
-        // Cycle through all channels and record the value of each in ChannelValues (ch_states)
-        for chan in 0..multiplexer.num_channels {
-            multiplexer.set_channel(chan); // NOTE: Changing channels takes at most 7ns
-            let data: u16 = adc1.read(&mut *cx.resources.analog_pin).unwrap();
-            ch_states.update_by_index(chan as u8, data);
-        }
-        rprintln!("{}", ch_states); // probe-rs goodness
+        // Cycle through all channels and record the value of each in ch_states.
+        // NOTE: Changing channels takes at most 7ns but high-impedance sources
+        // may need a settle time (see `Multiplexer::with_delay()`)
+        *ch_states = multiplexer.scan().unwrap();
+        // Clear the screen and move the cursor to the start before printing the table:
+        rprintln!("\x1B[2J\x1B[0HMultiplexer Channel Values:\n\n{}", ch_states); // probe-rs goodness
 
         if *LED_STATE {
             led.set_high().unwrap();
@@ -0,0 +1,131 @@
+//! Reading channels through an embedded-hal 0.2 ADC (`adc::OneShot`).
+//!
+//! `AnalogMultiplexer` bundles a `Multiplexer` together with the ADC
+//! and the analog pin that's connected to the multiplexer's common
+//! (`Z`) pin so that selecting a channel and reading it is a single
+//! call (and a single RTIC resource).
+
+use eh0::adc::{Channel, OneShot};
+
+use crate::snapshot::SnapshotSize;
+use crate::{Delay, Multiplexer, NoDelay, Output, ReadError, ScanOrder, Snapshot};
+
+/// A `Multiplexer` that owns the ADC and the analog pin connected to
+/// the multiplexer's common (`Z`) pin so it can read its channels
+/// directly.  Any settling delay configured on the `Multiplexer`
+/// (see `Multiplexer::with_delay()`) is applied before every read.
+pub struct AnalogMultiplexer<Pins, Adc, Z, D = NoDelay, const T: usize = 0> {
+    pub multiplexer: Multiplexer<Pins, D, T>,
+    pub adc: Adc,
+    pub pin: Z,
+}
+
+impl<Pins: Output, Adc, Z, D: Delay, const T: usize> AnalogMultiplexer<Pins, Adc, Z, D, T> {
+    /// Given a `Multiplexer`, an ADC (anything implementing
+    /// `adc::OneShot`), and the analog pin connected to the
+    /// multiplexer's common (`Z`) pin returns a new
+    /// `AnalogMultiplexer`.
+    pub fn new(multiplexer: Multiplexer<Pins, D, T>, adc: Adc, pin: Z) -> Self {
+        Self {
+            multiplexer,
+            adc,
+            pin,
+        }
+    }
+
+    /// Selects the given channel then reads it with the ADC.
+    /// Returns `ReadError::InvalidChannel` if the channel is out of
+    /// range for this multiplexer.
+    pub fn read_channel<ADC>(
+        &mut self,
+        channel: u8,
+    ) -> Result<u16, ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        self.multiplexer.try_set_channel(channel)?;
+        nb::block!(self.adc.read(&mut self.pin)).map_err(ReadError::Adc)
+    }
+
+    /// Reads every channel in order, storing each value at its
+    /// channel's index in `values`.  If `values` is shorter than
+    /// `num_channels` only that many channels will be read (any
+    /// extra slots are left untouched).
+    pub fn read_all<ADC>(
+        &mut self,
+        values: &mut [u16],
+    ) -> Result<(), ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        self.read_ordered(ScanOrder::Ascending, values)
+    }
+
+    /// Same as `read_all()` but visits the channels in the given order
+    /// (still storing each value at its channel's index in `values`).
+    /// Channels that aren't part of the order are left untouched.
+    pub fn read_ordered<ADC>(
+        &mut self,
+        order: ScanOrder,
+        values: &mut [u16],
+    ) -> Result<(), ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        for chan in order.channels(self.multiplexer.num_channels) {
+            if let Some(value) = values.get_mut(chan as usize) {
+                *value = self.read_channel(chan)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads every channel and returns them all as a `Snapshot`.  `N`
+    /// must match the multiplexer's number of channels (e.g.
+    /// `Snapshot<16>` for a 74HC4067); a mismatch fails to compile.
+    pub fn scan<ADC, const N: usize>(
+        &mut self,
+    ) -> Result<Snapshot<N>, ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        #[allow(clippy::let_unit_value)]
+        let () = SnapshotSize::<Pins, N>::OK;
+        self.scan_ordered(ScanOrder::Ascending)
+    }
+
+    /// Same as `scan()` but visits the channels in the given order.
+    /// Channels that aren't part of the order read as 0.
+    pub fn scan_ordered<ADC, const N: usize>(
+        &mut self,
+        order: ScanOrder,
+    ) -> Result<Snapshot<N>, ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        #[allow(clippy::let_unit_value)]
+        let () = SnapshotSize::<Pins, N>::OK;
+        let mut snapshot = Snapshot::new();
+        self.read_ordered(order, &mut snapshot.values)?;
+        Ok(snapshot)
+    }
+
+    /// Returns the `Multiplexer`, ADC, and analog pin (in that order)
+    pub fn release(self) -> (Multiplexer<Pins, D, T>, Adc, Z) {
+        (self.multiplexer, self.adc, self.pin)
+    }
+}
+
+impl<Pins: Output, D: Delay, const T: usize> Multiplexer<Pins, D, T> {
+    /// Turns this `Multiplexer` into an `AnalogMultiplexer` that owns
+    /// the given ADC and the analog pin connected to the multiplexer's
+    /// common (`Z`) pin.
+    pub fn with_adc<Adc, Z>(self, adc: Adc, pin: Z) -> AnalogMultiplexer<Pins, Adc, Z, D, T> {
+        AnalogMultiplexer::new(self, adc, pin)
+    }
+}
@@ -0,0 +1,203 @@
+//! Reading channels asynchronously (enable the `async` feature).
+//!
+//! `AsyncMultiplexer` awaits the settle time after switching channels
+//! (via embedded-hal-async's `DelayNs`) and the ADC conversion (via
+//! [`AsyncAdc`]) so other tasks keep running on executors like Embassy:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new(pins)
+//!     .unwrap()
+//!     .into_async(adc, analog_pin, embassy_time::Delay);
+//! multiplexer.multiplexer.settle_us = 5;
+//! let data = multiplexer.read_channel(5).await.unwrap();
+//! let mut all = [0u16; 16];
+//! multiplexer.scan(&mut all).await.unwrap();
+//! ```
+
+use embedded_hal_async::delay::DelayNs;
+
+use crate::{Multiplexer, NoDelay, Output, ReadError, ScanOrder};
+
+/// An ADC that can asynchronously read the analog pin connected to the
+/// multiplexer's common (`Z`) pin.  Implement it for your HAL's ADC
+/// (e.g. a small wrapper around an Embassy `Adc`).
+#[allow(async_fn_in_trait)]
+pub trait AsyncAdc<Z> {
+    /// The error returned if the conversion fails
+    type Error;
+    async fn read(&mut self, pin: &mut Z) -> Result<u16, Self::Error>;
+}
+
+/// A `Multiplexer` that owns an [`AsyncAdc`], the analog pin connected
+/// to the multiplexer's common (`Z`) pin, and an async delay provider
+/// so it can read channels without blocking.  The `Multiplexer`'s
+/// `settle_us` (and per-channel overrides) are awaited after every
+/// channel switch.
+pub struct AsyncMultiplexer<Pins, Adc, Z, D, const T: usize = 0> {
+    pub multiplexer: Multiplexer<Pins, NoDelay, T>,
+    pub adc: Adc,
+    pub pin: Z,
+    pub delay: D,
+}
+
+impl<Pins, Adc, Z, D, const T: usize> AsyncMultiplexer<Pins, Adc, Z, D, T>
+where
+    Pins: Output,
+    Adc: AsyncAdc<Z>,
+    D: DelayNs,
+{
+    pub fn new(multiplexer: Multiplexer<Pins, NoDelay, T>, adc: Adc, pin: Z, delay: D) -> Self {
+        Self {
+            multiplexer,
+            adc,
+            pin,
+            delay,
+        }
+    }
+
+    /// Selects the given channel, waits for it to settle (if it
+    /// changed or a failed write left the select pins in an unknown
+    /// state), then reads it with the ADC.  Returns
+    /// `ReadError::InvalidChannel` if the channel is out of range for
+    /// this multiplexer.
+    pub async fn read_channel(
+        &mut self,
+        channel: u8,
+    ) -> Result<u16, ReadError<Pins::Error, Adc::Error>> {
+        let switched = self.multiplexer.switching(channel);
+        self.multiplexer.try_set_channel(channel)?;
+        let settle_us = self.multiplexer.settle_time(channel);
+        if switched && settle_us > 0 {
+            self.delay.delay_us(settle_us).await;
+        }
+        self.adc.read(&mut self.pin).await.map_err(ReadError::Adc)
+    }
+
+    /// Reads every channel in order, storing each value at its
+    /// channel's index in `values`.  If `values` is shorter than
+    /// `num_channels` only that many channels will be read.
+    pub async fn scan(
+        &mut self,
+        values: &mut [u16],
+    ) -> Result<(), ReadError<Pins::Error, Adc::Error>> {
+        self.scan_ordered(ScanOrder::Ascending, values).await
+    }
+
+    /// Same as `scan()` but visits the channels in the given order
+    /// (still storing each value at its channel's index in `values`).
+    /// Channels that aren't part of the order are left untouched.
+    pub async fn scan_ordered(
+        &mut self,
+        order: ScanOrder<'_>,
+        values: &mut [u16],
+    ) -> Result<(), ReadError<Pins::Error, Adc::Error>> {
+        for chan in order.channels(self.multiplexer.num_channels) {
+            if let Some(value) = values.get_mut(chan as usize) {
+                *value = self.read_channel(chan).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the `Multiplexer`, ADC, analog pin, and delay (in that order)
+    pub fn release(self) -> (Multiplexer<Pins, NoDelay, T>, Adc, Z, D) {
+        (self.multiplexer, self.adc, self.pin, self.delay)
+    }
+}
+
+impl<Pins: Output, const T: usize> Multiplexer<Pins, NoDelay, T> {
+    /// Turns this `Multiplexer` into an `AsyncMultiplexer` that owns
+    /// the given ADC, the analog pin connected to the multiplexer's
+    /// common (`Z`) pin, and an async delay provider.
+    pub fn into_async<Adc, Z, D>(
+        self,
+        adc: Adc,
+        pin: Z,
+        delay: D,
+    ) -> AsyncMultiplexer<Pins, Adc, Z, D, T>
+    where
+        Adc: AsyncAdc<Z>,
+        D: DelayNs,
+    {
+        AsyncMultiplexer::new(self, adc, pin, delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockMux, MockZ};
+    use core::future::Future;
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    /// Polls a future until it's ready (good enough for these tests)
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Records how long it was asked to wait (yielding once each time)
+    struct MockDelay(Rc<RefCell<Vec<u32>>>);
+
+    impl DelayNs for MockDelay {
+        async fn delay_ns(&mut self, ns: u32) {
+            self.0.borrow_mut().push(ns / 1000);
+            let mut yielded = false;
+            core::future::poll_fn(|_| {
+                if yielded {
+                    Poll::Ready(())
+                } else {
+                    yielded = true;
+                    Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+
+    #[test]
+    fn read_channel_awaits_settle_time() {
+        let mock = MockMux::new();
+        let waits = Rc::new(RefCell::new(Vec::new()));
+        let mut multiplexer = Multiplexer::new(mock.pins8())
+            .unwrap()
+            .with_settle_times([None; 8]);
+        multiplexer.settle_us = 5;
+        multiplexer.set_settle_time(3, Some(20)).unwrap();
+        let mut multiplexer =
+            multiplexer.into_async(mock.adc(100), MockZ::<0>, MockDelay(waits.clone()));
+        assert_eq!(block_on(multiplexer.read_channel(3)), Ok(300));
+        assert_eq!(block_on(multiplexer.read_channel(3)), Ok(300));
+        assert_eq!(block_on(multiplexer.read_channel(4)), Ok(400));
+        assert_eq!(*waits.borrow(), [20, 5]);
+        assert_eq!(
+            block_on(multiplexer.read_channel(8)),
+            Err(ReadError::InvalidChannel(8))
+        );
+    }
+
+    #[test]
+    fn scan_reads_every_channel() {
+        let mock = MockMux::new();
+        let waits = Rc::new(RefCell::new(Vec::new()));
+        let mut multiplexer = Multiplexer::new(mock.pins16()).unwrap().into_async(
+            mock.adc(100),
+            MockZ::<0>,
+            MockDelay(waits),
+        );
+        let mut values = [0u16; 16];
+        block_on(multiplexer.scan(&mut values)).unwrap();
+        for (chan, value) in values.iter().enumerate() {
+            assert_eq!(*value, chan as u16 * 100);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! Banks of multiplexers that share their select lines.
+//!
+//! A common PCB layout wires `S0`-`S3` of several multiplexers in
+//! parallel, gives each chip its own `EN` pin, and ties all of their
+//! common (`Z`) pins to one ADC pin.  A `Bank` owns the shared select
+//! pins plus the `EN` pin of every chip and makes sure only one chip is
+//! ever enabled at a time.  It implements `Output` so it can be used
+//! anywhere a pin tuple can:
+//!
+//! ```ignore
+//! let bank = Bank::new((s0, s1, s2, s3, NoEnable), [en0, en1, en2, en3]);
+//! let mut multiplexer = Multiplexer::new(bank).unwrap();
+//! assert_eq!(multiplexer.num_channels, 64);
+//! multiplexer.set_channel(37).unwrap(); // Chip 2, channel 5
+//! ```
+
+use crate::{Output, Pin};
+
+/// Errors that can occur while driving a `Bank`'s pins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BankError<S, E> {
+    /// Setting one of the shared select pins failed
+    Select(S),
+    /// Setting the given chip's `EN` pin failed
+    Enable(u8, E),
+}
+
+/// `N` multiplexers sharing one set of select pins (`Sel`), each with
+/// its own `EN` pin.  Chip `n`'s channel `c` is addressed as channel
+/// `n * Sel::NUM_CHANNELS + c`.
+///
+/// Switching to a channel on another chip is break-before-make: the
+/// old chip is disabled before the select lines change and the new
+/// chip is only enabled afterwards.
+pub struct Bank<Sel, EN, const N: usize> {
+    /// The shared select pins.  If the chips' `EN` pins are all wired
+    /// separately use `NoEnable` as this tuple's `EN`.
+    pub select: Sel,
+    pub enables: [EN; N],
+    /// The chip that owns the active channel
+    chip: u8,
+    /// The chip whose `EN` pin is presently driven low (if any)
+    live: Option<u8>,
+    enabled: bool,
+}
+
+impl<Sel: Output, EN: Pin, const N: usize> Bank<Sel, EN, N> {
+    /// Returns a new `Bank` where `enables[n]` is chip `n`'s `EN` pin.
+    /// No pins are touched until it's used (`Multiplexer::new()` will
+    /// disable every chip but the first).  Using more than 255 channels
+    /// in total fails to compile once it's used with a `Multiplexer`.
+    pub fn new(select: Sel, enables: [EN; N]) -> Self {
+        Self {
+            select,
+            enables,
+            chip: 0,
+            live: None,
+            enabled: false,
+        }
+    }
+
+    /// Returns the chip that owns the active channel
+    pub fn chip(&self) -> u8 {
+        self.chip
+    }
+
+    /// Returns the shared select pins and every chip's `EN` pin
+    pub fn release(self) -> (Sel, [EN; N]) {
+        (self.select, self.enables)
+    }
+
+    /// Disables the previous chip (if `to` is on another chip), sets
+    /// the shared select pins (only the ones that change if they're
+    /// known to be on channel `from`), then enables `to`'s chip
+    fn select_channel(
+        &mut self,
+        from: Option<u8>,
+        to: u8,
+    ) -> Result<(), BankError<Sel::Error, EN::Error>> {
+        let chip = (to / Sel::NUM_CHANNELS) % N as u8;
+        if let Some(live) = self.live.filter(|live| *live != chip) {
+            self.disable_chip(live)?;
+            self.live = None;
+        }
+        let to = to % Sel::NUM_CHANNELS;
+        match from {
+            Some(from) => self.select.switch_channel(from % Sel::NUM_CHANNELS, to),
+            None => self.select.set_channel(to),
+        }
+        .map_err(BankError::Select)?;
+        self.chip = chip;
+        if self.enabled && self.live.is_none() {
+            self.enables[chip as usize]
+                .set_low()
+                .map_err(|e| BankError::Enable(chip, e))?;
+            self.live = Some(chip);
+        }
+        Ok(())
+    }
+
+    /// Brings the given chip's `EN` pin high to disable it
+    fn disable_chip(&mut self, chip: u8) -> Result<(), BankError<Sel::Error, EN::Error>> {
+        self.enables[chip as usize]
+            .set_high()
+            .map_err(|e| BankError::Enable(chip, e))
+    }
+}
+
+impl<Sel: Output, EN: Pin, const N: usize> Output for Bank<Sel, EN, N> {
+    type Error = BankError<Sel::Error, EN::Error>;
+    const NUM_CHANNELS: u8 = {
+        let num_channels = N * Sel::NUM_CHANNELS as usize;
+        assert!(
+            Sel::NUM_CHANNELS != 0,
+            "Bank needs select pins whose number of channels is known at compile time"
+        );
+        assert!(
+            num_channels <= u8::MAX as usize,
+            "Bank has more than 255 channels"
+        );
+        num_channels as u8
+    };
+    const HAS_ENABLE: bool = EN::CONNECTED;
+
+    /// Disables the previous chip (if the channel is on another chip),
+    /// sets the shared select pins, then enables the channel's chip
+    fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error> {
+        self.select_channel(None, channel)
+    }
+
+    /// Same as `set_channel()` but only the select pins whose level
+    /// changes are written
+    fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+        self.select_channel(Some(from), to)
+    }
+
+    /// Disables every other chip then enables the active one
+    fn enable(&mut self) -> Result<(), Self::Error> {
+        self.select.enable().map_err(BankError::Select)?;
+        let active = self.chip;
+        for chip in (0..N as u8).filter(|chip| *chip != active) {
+            self.disable_chip(chip)?;
+        }
+        self.enabled = true;
+        self.enables[active as usize]
+            .set_low()
+            .map_err(|e| BankError::Enable(active, e))?;
+        self.live = Some(active);
+        Ok(())
+    }
+
+    /// Disables every chip.  If that fails the `Bank` stays enabled
+    /// (any chip that couldn't be disabled is still considered live).
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        for chip in 0..N as u8 {
+            self.disable_chip(chip)?;
+            if self.live == Some(chip) {
+                self.live = None;
+            }
+        }
+        self.select.disable().map_err(BankError::Select)?;
+        self.enabled = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Line, MockError, MockMux, MockPin};
+    use crate::Multiplexer;
+
+    const L: bool = false;
+    const H: bool = true;
+
+    /// Returns a 2-chip bank of 8-channel multiplexers.  The chips'
+    /// `EN` pins are recorded as `Select(4)` and `Select(5)` so every
+    /// write lands in the same sequence.
+    fn bank(mock: &MockMux) -> Bank<(MockPin, MockPin, MockPin, MockPin), MockPin, 2> {
+        Bank::new(
+            mock.pins8(),
+            [mock.pin(Line::Select(4)), mock.pin(Line::Select(5))],
+        )
+    }
+
+    #[test]
+    fn new_enables_only_the_first_chip() {
+        let mock = MockMux::new();
+        let multiplexer = Multiplexer::new(bank(&mock)).unwrap();
+        assert_eq!(multiplexer.num_channels, 16);
+        assert_eq!(
+            mock.take_levels()[..3],
+            [
+                (Line::Enable, L),
+                (Line::Select(5), H),
+                (Line::Select(4), L)
+            ]
+        );
+    }
+
+    #[test]
+    fn switching_chips_is_break_before_make() {
+        let mock = MockMux::new();
+        let mut multiplexer = Multiplexer::new(bank(&mock)).unwrap();
+        mock.take_levels();
+        multiplexer.set_channel(13).unwrap();
+        assert_eq!(
+            mock.take_levels(),
+            [
+                (Line::Select(4), H), // Chip 0 off first
+                (Line::Select(0), H),
+                (Line::Select(2), H),
+                (Line::Select(5), L), // Then chip 1 on
+            ]
+        );
+        assert_eq!(multiplexer.pins.chip(), 1);
+        // Staying on the same chip only touches the select pins that change
+        multiplexer.set_channel(9).unwrap();
+        assert_eq!(mock.take_levels(), [(Line::Select(2), L)]);
+    }
+
+    #[test]
+    fn disabled_bank_keeps_every_chip_off() {
+        let mock = MockMux::new();
+        let mut multiplexer = Multiplexer::new(bank(&mock)).unwrap();
+        multiplexer.disable().unwrap();
+        mock.take_levels();
+        multiplexer.set_channel(10).unwrap();
+        assert!(mock
+            .take_levels()
+            .iter()
+            .all(|(line, _)| !matches!(line, Line::Select(4) | Line::Select(5))));
+        multiplexer.enable().unwrap();
+        assert_eq!(mock.level(Line::Select(4)), Some(H));
+        assert_eq!(mock.level(Line::Select(5)), Some(L));
+    }
+
+    #[test]
+    fn select_failure_leaves_every_chip_off() {
+        let mock = MockMux::new();
+        let mut multiplexer = Multiplexer::new(bank(&mock)).unwrap();
+        mock.set_failing(Line::Select(1), true);
+        assert_eq!(
+            multiplexer.set_channel(10),
+            Err(BankError::Select(crate::Error::S1(MockError(
+                Line::Select(1)
+            ))))
+        );
+        assert_eq!(mock.level(Line::Select(4)), Some(H));
+        assert_eq!(mock.level(Line::Select(5)), Some(H));
+        mock.set_failing(Line::Select(1), false);
+        multiplexer.set_channel(10).unwrap();
+        assert_eq!(mock.level(Line::Select(5)), Some(L));
+    }
+
+    #[test]
+    fn failed_disable_leaves_the_bank_enabled() {
+        let mock = MockMux::new();
+        let mut multiplexer = Multiplexer::new(bank(&mock)).unwrap();
+        multiplexer.set_channel(2).unwrap();
+        mock.set_failing(Line::Select(5), true);
+        assert_eq!(
+            multiplexer.disable(),
+            Err(BankError::Enable(1, MockError(Line::Select(5))))
+        );
+        assert!(multiplexer.enabled);
+        assert_eq!(mock.level(Line::Select(4)), Some(H)); // Chip 0 went off
+        mock.set_failing(Line::Select(5), false);
+        // Still enabled so switching channels turns chip 0 back on
+        multiplexer.set_channel(3).unwrap();
+        assert_eq!(mock.level(Line::Select(4)), Some(L));
+    }
+}
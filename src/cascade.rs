@@ -0,0 +1,190 @@
+//! Trees of multiplexers (mux-of-muxes) with a flat channel address space.
+//!
+//! A `Cascade` feeds the common (`Z`) pins of `L` "leaf" multiplexers
+//! into channels `0..L` of a "root" multiplexer.  It implements `Output`
+//! so it can be used anywhere a pin tuple can, e.g. four 74HC4067s under
+//! a fifth one give 64 channels on a single ADC pin:
+//!
+//! ```ignore
+//! let cascade = Cascade::new(root_pins, [leaf0_pins, leaf1_pins, leaf2_pins, leaf3_pins]);
+//! let mut multiplexer = Multiplexer::new(cascade).unwrap();
+//! assert_eq!(multiplexer.num_channels, 64);
+//! multiplexer.set_channel(37).unwrap(); // Leaf 2, channel 5 (root on channel 2)
+//! ```
+//!
+//! Since a `Cascade` is itself an `Output` it can be used as the root or
+//! the leaves of another `Cascade` for deeper trees (up to 255 channels).
+
+use crate::Output;
+
+/// Errors that can occur while driving a `Cascade`'s pins
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CascadeError<R, L> {
+    /// Setting one of the root multiplexer's pins failed
+    Root(R),
+    /// Setting one of the given leaf multiplexer's pins failed
+    Leaf(u8, L),
+}
+
+/// A root multiplexer (`Root`) whose channels `0..L` are fed by the
+/// common pins of `L` leaf multiplexers (`Leaf`).  Leaf `n`'s channel
+/// `c` is addressed as channel `n * Leaf::NUM_CHANNELS + c`.
+pub struct Cascade<Root, Leaf, const L: usize> {
+    pub root: Root,
+    pub leaves: [Leaf; L],
+}
+
+impl<Root: Output, Leaf: Output, const L: usize> Cascade<Root, Leaf, L> {
+    /// Returns a new `Cascade` where `leaves[n]` is wired to the root
+    /// multiplexer's channel `n`.  Using more leaves than the root has
+    /// channels (or more than 255 channels in total) fails to compile
+    /// once it's used with a `Multiplexer`.
+    pub fn new(root: Root, leaves: [Leaf; L]) -> Self {
+        Self { root, leaves }
+    }
+
+    /// Returns the root and leaf multiplexers' pins
+    pub fn release(self) -> (Root, [Leaf; L]) {
+        (self.root, self.leaves)
+    }
+}
+
+impl<Root: Output, Leaf: Output, const L: usize> Output for Cascade<Root, Leaf, L> {
+    type Error = CascadeError<Root::Error, Leaf::Error>;
+    const NUM_CHANNELS: u8 = {
+        let num_channels = L * Leaf::NUM_CHANNELS as usize;
+        assert!(
+            Root::NUM_CHANNELS != 0 && Leaf::NUM_CHANNELS != 0,
+            "Cascade needs multiplexers whose number of channels is known at compile time"
+        );
+        assert!(
+            L <= Root::NUM_CHANNELS as usize,
+            "Cascade has more leaves than the root multiplexer has channels"
+        );
+        assert!(
+            num_channels <= u8::MAX as usize,
+            "Cascade has more than 255 channels"
+        );
+        num_channels as u8
+    };
+    const HAS_ENABLE: bool = Root::HAS_ENABLE || Leaf::HAS_ENABLE;
+
+    /// Selects the channel on its leaf first, then connects that leaf
+    /// to the common pin via the root
+    fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error> {
+        let leaf = channel / Leaf::NUM_CHANNELS;
+        self.leaves[leaf as usize % L]
+            .set_channel(channel % Leaf::NUM_CHANNELS)
+            .map_err(|e| CascadeError::Leaf(leaf, e))?;
+        self.root.set_channel(leaf).map_err(CascadeError::Root)
+    }
+
+    /// Only touches the leaf (and the root) if the channel is on
+    /// another leaf; otherwise just the leaf's select pins that change
+    fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+        let (from_leaf, leaf) = (from / Leaf::NUM_CHANNELS, to / Leaf::NUM_CHANNELS);
+        if from_leaf != leaf {
+            return self.set_channel(to);
+        }
+        self.leaves[leaf as usize % L]
+            .switch_channel(from % Leaf::NUM_CHANNELS, to % Leaf::NUM_CHANNELS)
+            .map_err(|e| CascadeError::Leaf(leaf, e))
+    }
+
+    /// Enables every leaf then the root
+    fn enable(&mut self) -> Result<(), Self::Error> {
+        for (leaf, pins) in self.leaves.iter_mut().enumerate() {
+            pins.enable()
+                .map_err(|e| CascadeError::Leaf(leaf as u8, e))?;
+        }
+        self.root.enable().map_err(CascadeError::Root)
+    }
+
+    /// Disables the root then every leaf
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        self.root.disable().map_err(CascadeError::Root)?;
+        for (leaf, pins) in self.leaves.iter_mut().enumerate() {
+            pins.disable()
+                .map_err(|e| CascadeError::Leaf(leaf as u8, e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Line, MockError, MockMux};
+    use crate::{ChannelError, Error, Multiplexer};
+
+    #[test]
+    fn flat_channels_decode_into_leaf_and_root() {
+        let root = MockMux::new();
+        let leaves = [MockMux::new(), MockMux::new(), MockMux::new()];
+        let cascade = Cascade::new(
+            root.pins16(),
+            [leaves[0].pins8(), leaves[1].pins8(), leaves[2].pins8()],
+        );
+        let mut multiplexer = Multiplexer::new(cascade).unwrap();
+        assert_eq!(multiplexer.num_channels, 24);
+        multiplexer.set_channel(21).unwrap();
+        assert_eq!(root.selected(), Some(2));
+        assert_eq!(leaves[2].selected(), Some(5));
+        leaves[2].clear();
+        multiplexer.set_channel(3).unwrap();
+        assert_eq!(root.selected(), Some(0));
+        assert_eq!(leaves[0].selected(), Some(3));
+        assert!(leaves[2].transitions().is_empty()); // Untouched
+        multiplexer.disable().unwrap();
+        assert_eq!(root.selected(), None);
+        assert!(leaves.iter().all(|leaf| leaf.selected().is_none()));
+    }
+
+    #[test]
+    fn leaf_errors_identify_the_leaf() {
+        let root = MockMux::new();
+        let leaves = [MockMux::new(), MockMux::new()];
+        let cascade = Cascade::new(root.pins8(), [leaves[0].pins16(), leaves[1].pins16()]);
+        let mut multiplexer = Multiplexer::new(cascade).unwrap();
+        leaves[1].set_failing(Line::Select(2), true);
+        assert_eq!(
+            multiplexer.set_channel(20),
+            Err(CascadeError::Leaf(1, Error::S2(MockError(Line::Select(2)))))
+        );
+        assert_eq!(root.selected(), Some(0)); // Root never switched
+        assert_eq!(multiplexer.active_channel, 0);
+    }
+
+    #[test]
+    fn settle_times_cover_every_channel() {
+        let root = MockMux::new();
+        let leaves = [
+            MockMux::new(),
+            MockMux::new(),
+            MockMux::new(),
+            MockMux::new(),
+        ];
+        let cascade = Cascade::new(
+            root.pins16(),
+            [
+                leaves[0].pins16(),
+                leaves[1].pins16(),
+                leaves[2].pins16(),
+                leaves[3].pins16(),
+            ],
+        );
+        let mut multiplexer = Multiplexer::new(cascade)
+            .unwrap()
+            .with_settle_times([None; 64]);
+        assert_eq!(multiplexer.num_channels, 64);
+        multiplexer.set_settle_time(40, Some(50)).unwrap();
+        multiplexer.set_settle_time(63, Some(70)).unwrap();
+        assert_eq!(multiplexer.settle_time(40), 50);
+        assert_eq!(multiplexer.settle_time(63), 70);
+        assert_eq!(multiplexer.settle_time(8), 0);
+        assert_eq!(
+            multiplexer.set_settle_time(64, Some(50)),
+            Err(ChannelError::InvalidChannel(64))
+        );
+    }
+}
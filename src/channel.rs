@@ -0,0 +1,47 @@
+//! Bounds-checked channel indices.
+
+use core::convert::TryFrom;
+
+/// A channel index that's guaranteed to be valid for a multiplexer
+/// with `N` channels (e.g. `Channel<16>` for a 74HC4067 or
+/// `Channel<8>` for a 74HC4051).  Use it with `Multiplexer::select()`
+/// so an 8-channel index can't be handed to a 16-channel multiplexer
+/// (or vice versa) and out-of-range channels are caught up front.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Channel<const N: u8>(u8);
+
+impl<const N: u8> Channel<N> {
+    /// Returns the given channel if it's in range (`0..N`)
+    pub const fn new(index: u8) -> Option<Self> {
+        if index < N {
+            Some(Self(index))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the channel's index (`0..N`)
+    pub const fn index(self) -> u8 {
+        self.0
+    }
+
+    /// Returns an iterator over every channel (`0..N`)
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..N).map(Self)
+    }
+}
+
+impl<const N: u8> TryFrom<u8> for Channel<N> {
+    type Error = u8;
+
+    /// Returns the out-of-range index as the error
+    fn try_from(index: u8) -> Result<Self, Self::Error> {
+        Self::new(index).ok_or(index)
+    }
+}
+
+impl<const N: u8> From<Channel<N>> for u8 {
+    fn from(channel: Channel<N>) -> u8 {
+        channel.0
+    }
+}
@@ -0,0 +1,45 @@
+//! Delay providers for letting the multiplexer's output settle after
+//! switching channels.
+//!
+//! Changing channels only takes a few nanoseconds but with
+//! high-impedance sources (and the ADC's sample capacitor) it can
+//! take microseconds before the common (`Z`) pin actually reflects
+//! the newly-selected channel.  Give your `Multiplexer` a delay via
+//! `Multiplexer::with_delay()` to wait that long after every switch.
+
+/// Something that can wait a given number of microseconds.  It's
+/// implemented for every embedded-hal 1.0 `DelayNs` (`eh1` feature)
+/// and for embedded-hal 0.2 `DelayUs<u32>` providers wrapped in an
+/// [`Eh0Delay`] (`eh0` feature).
+pub trait Delay {
+    /// `false` if `delay_us()` doesn't actually wait (like `NoDelay`)
+    const WAITS: bool = true;
+    fn delay_us(&mut self, us: u32);
+}
+
+#[cfg(feature = "eh1")]
+impl<D: eh1::delay::DelayNs> Delay for D {
+    fn delay_us(&mut self, us: u32) {
+        eh1::delay::DelayNs::delay_us(self, us)
+    }
+}
+
+/// Wraps an embedded-hal 0.2 `blocking::delay::DelayUs<u32>` so it
+/// can be used as a `Multiplexer`'s settling delay
+#[cfg(feature = "eh0")]
+pub struct Eh0Delay<D>(pub D);
+
+#[cfg(feature = "eh0")]
+impl<D: eh0::blocking::delay::DelayUs<u32>> Delay for Eh0Delay<D> {
+    fn delay_us(&mut self, us: u32) {
+        self.0.delay_us(us)
+    }
+}
+
+/// The default "delay" which doesn't wait at all
+pub struct NoDelay;
+
+impl Delay for NoDelay {
+    const WAITS: bool = false;
+    fn delay_us(&mut self, _us: u32) {}
+}
@@ -0,0 +1,147 @@
+//! Dual multiplexers (e.g. the 74HC4052) whose select lines switch two
+//! sections (X and Y) at once.
+//!
+//! The 74HC4052's two select lines pick the same channel (0-3) on both
+//! of its 4:1 switches so it's commonly used for differential or paired
+//! measurements.  Drive it like any other 4-channel multiplexer
+//! (`(s0, s1, en)`) then hand the `Multiplexer` the ADC and the analog
+//! pins connected to the X and Y commons:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new((s0, s1, en)).unwrap().with_dual_adc(adc1, pa0, pa1);
+//! let (x, y) = multiplexer.read_pair(2).unwrap(); // Both halves of channel 2
+//! let diff: i32 = multiplexer.read_difference(2).unwrap(); // x - y
+//! ```
+
+use eh0::adc::{Channel, OneShot};
+
+use crate::{ChannelError, Delay, Multiplexer, NoDelay, Output, ReadError};
+
+/// A `Multiplexer` driving a dual multiplexer (e.g. a 74HC4052) that
+/// owns the ADC and the analog pins connected to its X and Y common
+/// pins.  Selecting a channel switches both sections together and any
+/// settling delay configured on the `Multiplexer` is applied once per
+/// channel switch.
+pub struct DualMultiplexer<Pins, Adc, X, Y, D = NoDelay, const T: usize = 0> {
+    pub multiplexer: Multiplexer<Pins, D, T>,
+    pub adc: Adc,
+    /// The analog pin connected to the X section's common pin
+    pub x: X,
+    /// The analog pin connected to the Y section's common pin
+    pub y: Y,
+}
+
+impl<Pins: Output, Adc, X, Y, D: Delay, const T: usize> DualMultiplexer<Pins, Adc, X, Y, D, T> {
+    pub fn new(multiplexer: Multiplexer<Pins, D, T>, adc: Adc, x: X, y: Y) -> Self {
+        Self {
+            multiplexer,
+            adc,
+            x,
+            y,
+        }
+    }
+
+    /// Selects the given channel on both the X and Y sections.
+    /// Returns `ChannelError::InvalidChannel` if the channel is out of
+    /// range for this multiplexer.
+    pub fn set_channel(&mut self, channel: u8) -> Result<(), ChannelError<Pins::Error>> {
+        self.multiplexer.try_set_channel(channel)
+    }
+
+    /// Selects the given channel then reads the X and Y commons (in
+    /// that order) with the ADC
+    pub fn read_pair<ADC, E>(
+        &mut self,
+        channel: u8,
+    ) -> Result<(u16, u16), ReadError<Pins::Error, E>>
+    where
+        Adc: OneShot<ADC, u16, X, Error = E> + OneShot<ADC, u16, Y, Error = E>,
+        X: Channel<ADC>,
+        Y: Channel<ADC>,
+    {
+        self.set_channel(channel)?;
+        let x = nb::block!(self.adc.read(&mut self.x)).map_err(ReadError::Adc)?;
+        let y = nb::block!(self.adc.read(&mut self.y)).map_err(ReadError::Adc)?;
+        Ok((x, y))
+    }
+
+    /// Same as `read_pair()` but returns the difference (X - Y) for
+    /// differential measurements
+    pub fn read_difference<ADC, E>(&mut self, channel: u8) -> Result<i32, ReadError<Pins::Error, E>>
+    where
+        Adc: OneShot<ADC, u16, X, Error = E> + OneShot<ADC, u16, Y, Error = E>,
+        X: Channel<ADC>,
+        Y: Channel<ADC>,
+    {
+        let (x, y) = self.read_pair(channel)?;
+        Ok(x as i32 - y as i32)
+    }
+
+    /// Returns the `Multiplexer`, ADC, and the X and Y analog pins (in
+    /// that order)
+    pub fn release(self) -> (Multiplexer<Pins, D, T>, Adc, X, Y) {
+        (self.multiplexer, self.adc, self.x, self.y)
+    }
+}
+
+impl<Pins: Output, D: Delay, const T: usize> Multiplexer<Pins, D, T> {
+    /// Turns this `Multiplexer` into a `DualMultiplexer` that owns the
+    /// given ADC and the analog pins connected to the X and Y common
+    /// pins of a dual multiplexer (e.g. a 74HC4052).
+    pub fn with_dual_adc<Adc, X, Y>(
+        self,
+        adc: Adc,
+        x: X,
+        y: Y,
+    ) -> DualMultiplexer<Pins, Adc, X, Y, D, T> {
+        DualMultiplexer::new(self, adc, x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Line, MockAdc, MockMux, MockPin, MockZ};
+
+    /// Returns a 74HC4052 (two select lines plus `EN`) that reads back
+    /// `1000 + 10 * channel` on X and `10 * channel` on Y
+    fn mux4052(
+        mock: &MockMux,
+    ) -> DualMultiplexer<(MockPin, MockPin, MockPin), MockAdc, MockZ<1>, MockZ<0>> {
+        let pins = (
+            mock.pin(Line::Select(0)),
+            mock.pin(Line::Select(1)),
+            mock.pin(Line::Enable),
+        );
+        Multiplexer::new(pins)
+            .unwrap()
+            .with_dual_adc(mock.adc(10), MockZ::<1>, MockZ::<0>)
+    }
+
+    #[test]
+    fn reads_both_sections_of_a_channel() {
+        let mock = MockMux::new();
+        let mut multiplexer = mux4052(&mock);
+        assert_eq!(multiplexer.multiplexer.num_channels, 4);
+        for chan in 0..4 {
+            assert_eq!(
+                multiplexer.read_pair(chan),
+                Ok((1000 + 10 * chan as u16, 10 * chan as u16))
+            );
+            assert_eq!(multiplexer.read_difference(chan), Ok(1000));
+        }
+        assert_eq!(multiplexer.read_pair(4), Err(ReadError::InvalidChannel(4)));
+    }
+
+    #[test]
+    fn one_switch_per_pair() {
+        let mock = MockMux::new();
+        let mut multiplexer = mux4052(&mock);
+        multiplexer.set_channel(1).unwrap();
+        let seq = mock.seq();
+        multiplexer.read_pair(1).unwrap();
+        assert_eq!(mock.seq(), seq); // Already on channel 1
+        multiplexer.read_pair(3).unwrap();
+        assert_eq!(mock.seq() - seq, 1); // Only S1 changes
+    }
+}
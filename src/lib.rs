@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 //! This crate provides an interface, `Multiplexer` that makes it trivially easy
 //! to select channels on any given 74HC4051 or 74HC4067 series analog multiplexer.
 //! Internally it keeps track of each multiplexer's state, allowing you to
@@ -7,9 +7,9 @@
 //!
 //! # Example using a 74HC4067 with a Blue Pill (stm32f104) board
 //!
-//! ```
+//! ```ignore
 //! // NOTE: This is pseudocode. It's just meant to get the concept across :)
-//! use analog_multiplexer::Multiplexer; // Important part
+//! use analog_multiplexer::{Eh0Pin, Multiplexer}; // Important part
 //!
 //! use stm32f1xx_hal::gpio::State;
 //! // The pins we're using:
@@ -54,13 +54,14 @@
 //!         .into_push_pull_output_with_state(&mut gpiob.crl, State::Low);
 //!     // TIP: Just run a wire from EN to GND to keep it enabled all the time
 //!     // Multiplexer pins are given as a tuple in the order S0-S3 then enable pin (EN):
-//!     let pins = (s0,s1,s2,s3,en); // For 16-channel
-//!     // let pins = (s0,s1,s2,en); // For 8-channel
-//!     let mut multiplexer = Multiplexer::new(pins); // The important part!
-//!     multiplexer.enable(); // Make sure it's enabled (if using EN pin)
+//!     // NOTE: stm32f1xx_hal 0.7 is an embedded-hal 0.2 HAL so its pins get wrapped in Eh0Pin
+//!     let pins = (Eh0Pin(s0),Eh0Pin(s1),Eh0Pin(s2),Eh0Pin(s3),Eh0Pin(en)); // For 16-channel
+//!     // let pins = (Eh0Pin(s0),Eh0Pin(s1),Eh0Pin(s2),Eh0Pin(en)); // For 8-channel
+//!     let mut multiplexer = Multiplexer::new(pins).unwrap(); // The important part!
+//!     multiplexer.enable().unwrap(); // Make sure it's enabled (if using EN pin)
 //!     loop {
 //!         for chan in 0..multiplexer.num_channels {
-//!             multiplexer.set_channel(chan); // Change the channel
+//!             multiplexer.set_channel(chan).unwrap(); // Change the channel
 //!             let data: u16 = adc1.read(&mut *analog_pin).unwrap();
 //!             // Do something with the data here
 //!         }
@@ -71,191 +72,876 @@
 //!
 //! **NOTE:** There's a working Blue Pill/RTIC example in the `examples` directory.
 //!
-
-extern crate embedded_hal as hal;
+//! # Reading channels
+//!
+//! If your HAL's ADC implements the embedded-hal 0.2 `adc::OneShot` trait
+//! you can hand it (and the analog pin connected to `Z`) to the multiplexer
+//! and let it do the reading for you:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new(pins).unwrap().with_adc(adc1, analog_pin);
+//! let data: u16 = multiplexer.read_channel(5).unwrap(); // Selects channel 5 then reads it
+//! let mut all = [0u16; 16];
+//! multiplexer.read_all(&mut all).unwrap(); // Reads every channel
+//! let snapshot: Snapshot<16> = multiplexer.scan().unwrap(); // Same but sized to the device
+//! let ch5 = snapshot[5]; // Indexable by channel, iterable as (channel, value) pairs
+//! rprintln!("{}", snapshot); // ...and pretty-prints as a table
+//! ```
+//!
+//! # Scan order
+//!
+//! Only the select pins whose level changes get written when switching
+//! channels.  To keep that to one pin per switch scan in Gray code order
+//! (or any other `ScanOrder`, including a custom permutation or subset).
+//! Values are still stored by channel:
+//!
+//! ```ignore
+//! let snapshot: Snapshot<16> = multiplexer.scan_ordered(ScanOrder::Gray).unwrap();
+//! multiplexer.read_ordered(ScanOrder::Custom(&[3, 12, 7]), &mut all).unwrap();
+//! ```
+//!
+//! If the pins' state is ever unknown (e.g. an I/O expander browned out)
+//! `resync()` drives every pin again.
+//!
+//! # Atomic select updates
+//!
+//! Implement `SelectPort` for a GPIO port (or an I/O expander's output
+//! latch) and wrap it in a `PortOutput` so every select line changes in
+//! a single write instead of passing through intermediate channels:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new(PortOutput::new(port_b, en)).unwrap();
+//! ```
+//!
+//! # Break-before-make switching
+//!
+//! When the multiplexer is used bidirectionally (or the sensors don't like
+//! being briefly connected to each other) turn on break-before-make
+//! switching and it'll be disabled while the select pins change, then
+//! re-enabled (skipped if `EN` is `NoEnable`, but not for a `DummyPin`
+//! `EN`).  A guard time before re-enabling needs a delay provider:
+//!
+//! ```ignore
+//! multiplexer.set_break_before_make(true); // Re-enable right away
+//! let mut multiplexer = multiplexer.with_delay(delay, 0);
+//! multiplexer.set_break_before_make_guard(1); // Wait 1µs before re-enabling
+//! ```
+//!
+//! # Inverted pins
+//!
+//! Wrap any pin in `Inverted` if it goes through an inverting level
+//! shifter (or for parts with an active-high enable):
+//!
+//! ```ignore
+//! let pins = (Inverted(s0), Inverted(s1), Inverted(s2), Inverted(s3), Inverted(en));
+//! let mut multiplexer = Multiplexer::new(pins).unwrap();
+//! ```
+//!
+//! # Arrays and type-erased pins
+//!
+//! Pins picked at runtime (e.g. from a board revision ID) can be given as
+//! an array of select pins (or a `SelectSlice` when even the number of
+//! them is only known at runtime) wrapped in a `PortOutput`.  That's on
+//! purpose: a `([S; N], EN)` impl of `Output` would overlap the
+//! `(S0, EN)` tuple impl since embedded-hal could make arrays (or
+//! slices) `OutputPin`s too.
+//!
+//! ```ignore
+//! let select = [s0, s1, s2, s3]; // [ErasedPin; 4]
+//! let mut multiplexer = Multiplexer::new(PortOutput::new(select, en)).unwrap();
+//! let mut pins: [&mut dyn OutputPin<Error = E>; 3] = [&mut s0, &mut s1, &mut s2];
+//! let select = SelectSlice::new(&mut pins[..num_select_pins]).unwrap(); // Sized at runtime
+//! let mut multiplexer = Multiplexer::new(PortOutput::new(select, en)).unwrap();
+//! ```
+//!
+//! # Remapping select lines and channels
+//!
+//! If the select pins were wired out of order wrap them in `Rewired`.  To
+//! make channel numbers match the labels on the silkscreen use a
+//! `ChannelMap`:
+//!
+//! ```ignore
+//! let pins = Rewired::new((pb3, pb4, pb5, pb6, en), [1, 0, 2, 3]).unwrap(); // PB3 -> S1, PB4 -> S0
+//! let pins = ChannelMap::new(pins, INPUT_FOR_LABEL).unwrap(); // [u8; 16]
+//! ```
+//!
+//! # Settling time
+//!
+//! With high-impedance sources the output can take microseconds to settle
+//! after switching channels.  Give the multiplexer a delay provider (any
+//! embedded-hal 1.0 `DelayNs` or an embedded-hal 0.2 `DelayUs<u32>` wrapped
+//! in `Eh0Delay`) and it'll wait after every channel switch:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new(pins)
+//!     .unwrap()
+//!     .with_delay(delay, 5) // 5µs
+//!     .with_settle_times([None; 16]); // Per-channel overrides (opt-in)
+//! multiplexer.set_settle_time(12, Some(50)).unwrap(); // Channel 12 has a really slow source
+//! ```
+//!
+//! # Cascading multiplexers
+//!
+//! A `Cascade` wires the common pins of several "leaf" multiplexers into
+//! the channels of a "root" multiplexer and addresses them all with one
+//! flat channel index.  It's an `Output` so it works with everything above:
+//!
+//! ```ignore
+//! let cascade = Cascade::new(root_pins, [leaf0_pins, leaf1_pins, leaf2_pins, leaf3_pins]);
+//! let mut multiplexer = Multiplexer::new(cascade).unwrap(); // 64 channels
+//! multiplexer.set_channel(37).unwrap(); // Leaf 2, channel 5
+//! ```
+//!
+//! # Banks sharing select lines
+//!
+//! A `Bank` drives several multiplexers whose select lines are wired in
+//! parallel (each with its own `EN` pin and a shared common pin), only
+//! ever enabling one chip at a time:
+//!
+//! ```ignore
+//! let bank = Bank::new((s0, s1, s2, s3, NoEnable), [en0, en1, en2, en3]);
+//! let mut multiplexer = Multiplexer::new(bank).unwrap(); // 64 channels
+//! ```
+//!
+//! # Dual multiplexers
+//!
+//! The 74HC4052's two select lines switch its X and Y sections together.
+//! Drive it like a 4-channel multiplexer and a `DualMultiplexer` reads
+//! both commons for paired or differential measurements:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new((s0, s1, en)).unwrap().with_dual_adc(adc1, pa0, pa1);
+//! let (x, y) = multiplexer.read_pair(2).unwrap();
+//! let diff: i32 = multiplexer.read_difference(2).unwrap(); // x - y
+//! ```
+//!
+//! # Parallel banks
+//!
+//! When several multiplexers share their select lines but each feed a
+//! separate ADC input a `ParallelMultiplexer` reads every bank after each
+//! channel switch:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new(pins).unwrap().with_banks((adc1, (pa0, pa1, pa2, pa3)));
+//! let snapshot: [Snapshot<16>; 4] = multiplexer.scan().unwrap(); // snapshot[bank][channel]
+//! ```
+//!
+//! # Non-blocking scanning
+//!
+//! For bare-metal loops without an async runtime `Scanner` advances a scan
+//! by one step per `poll()` (timing the settle time with a tick source
+//! instead of blocking) and returns `nb::Error::WouldBlock` until a full
+//! `Snapshot` is ready:
+//!
+//! ```ignore
+//! let mut scanner: Scanner<_, _, _, _, 16> =
+//!     Scanner::new(multiplexer.with_adc(adc1, analog_pin), || timer.now_us());
+//! if let Ok(snapshot) = scanner.poll() {
+//!     // Do something with the snapshot
+//! }
+//! ```
+//!
+//! # Cargo features
+//!
+//! * `eh1` (default): `Output` is implemented for tuples of embedded-hal 1.0
+//!   `OutputPin`s.
+//! * `eh0` (default): Provides `Eh0Pin` so embedded-hal 0.2 `digital::v2::OutputPin`s
+//!   can be used too (even mixed with 1.0 pins in the same tuple) along with
+//!   `AnalogMultiplexer`, `DualMultiplexer`, `ParallelMultiplexer`, and
+//!   `Scanner` (which need the 0.2 `adc::OneShot` trait).
+//! * `async`: Provides `AsyncMultiplexer` which awaits the settle time (via
+//!   embedded-hal-async's `DelayNs`) and ADC conversions (via `AsyncAdc`).
+//! * `mock`: Provides `mock::MockMux` which hands out recording `MockPin`s and
+//!   reports which channel is electrically selected at any time (for unit
+//!   testing code built on `Multiplexer` on the host; needs `std`).
+//!   With `eh0` also enabled `sim::Simulator` provides a simulated multiplexer
+//!   with virtual analog sources and an `adc::OneShot` ADC.
+//!
 
 use core::convert::Infallible;
-use hal::digital::v2::OutputPin;
+use core::fmt;
+use core::marker::PhantomData;
+
+mod bank;
+pub use bank::{Bank, BankError};
+mod cascade;
+pub use cascade::{Cascade, CascadeError};
+mod channel;
+pub use channel::Channel;
+mod delay;
+mod order;
+pub use order::{Channels, ScanOrder};
+mod port;
+pub use port::{PortError, PortOutput, SelectPort, SelectSlice};
+mod remap;
+pub use remap::{ChannelMap, Rewired};
+mod snapshot;
+pub use snapshot::Snapshot;
+#[cfg(feature = "eh0")]
+pub use delay::Eh0Delay;
+pub use delay::{Delay, NoDelay};
+
+#[cfg(feature = "mock")]
+extern crate std;
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+#[cfg(all(any(test, feature = "mock"), feature = "eh0"))]
+pub mod sim;
+#[cfg(all(test, feature = "eh1"))]
+mod tests;
+#[cfg(feature = "async")]
+mod asynch;
+#[cfg(feature = "async")]
+pub use asynch::{AsyncAdc, AsyncMultiplexer};
+#[cfg(feature = "eh0")]
+mod analog;
+#[cfg(feature = "eh0")]
+pub use analog::AnalogMultiplexer;
+#[cfg(feature = "eh0")]
+mod dual;
+#[cfg(feature = "eh0")]
+pub use dual::DualMultiplexer;
+#[cfg(feature = "eh0")]
+mod parallel;
+#[cfg(feature = "eh0")]
+pub use parallel::{BankInputs, ParallelMultiplexer};
+#[cfg(feature = "eh0")]
+mod scanner;
+#[cfg(feature = "eh0")]
+pub use scanner::{Clock, ScanState, Scanner};
+#[cfg(feature = "eh0")]
+mod shared;
+#[cfg(feature = "eh0")]
+pub use shared::{Mux, MuxAdc, MuxPin, MuxPins, SharedMultiplexer};
 
 /// Provides an interface for setting the active channel
 /// and enabling/disabling an 8-channel (74HC4051) or
-/// 16-channel (74HC4067) analog multiplexer.  It also
+/// 16-channel (74HC4067) analog multiplexer (or anything
+/// else from 2 to 32 channels).  It also
 /// keeps track of which channel is currently active
 /// (`active_channel`) and provides a convenient
 /// `num_channels` field that can be used to iterate
 /// over all the multiplexer's channels.
-pub struct Multiplexer<Pins> {
+///
+/// Optionally it can wait for the output to settle after
+/// switching channels (see `with_delay()`), overriding that time for
+/// individual channels (see `with_settle_times()`; `T` is the size of
+/// that table which takes up no RAM until it's used).
+pub struct Multiplexer<Pins, D = NoDelay, const T: usize = 0> {
     pub pins: Pins,
     pub num_channels: u8,
     pub active_channel: u8,
     pub enabled: bool,
+    pub delay: D,
+    /// How long to wait (in microseconds) after switching channels
+    pub settle_us: u32,
+    /// Per-channel overrides of `settle_us` (for slow sources)
+    pub channel_settle_us: [Option<u32>; T],
+    /// Break-before-make switching: if set, the multiplexer is disabled
+    /// while the select pins change and re-enabled after waiting this
+    /// long (in microseconds).  It's skipped if there's no `EN` pin.
+    break_before_make: Option<u32>,
+    /// `true` if the select pins are known to be driven to
+    /// `active_channel` (so only the pins that change get written)
+    synced: bool,
 }
 
-/// A trait so we can support both 8-channel and 16-channel
-/// multiplexers simultaneously by merely instantiating them
-/// with a 5 (16-channel) or 4 (8-channel) member tuple of
-/// `OutputPin`s.
-pub trait Output {
-    fn set_channel(&mut self, channel: u8);
-    fn enable(&mut self);
-    fn disable(&mut self);
-    fn num_channels(&self) -> u8;
+/// Errors that can occur while driving the multiplexer's pins.
+/// Each variant identifies the pin that failed and carries the
+/// error that was returned by its `OutputPin` implementation.
+///
+/// Every pin can have its own error type (e.g. `S0`-`S2` on native
+/// GPIO with an `Infallible` error and `S3` plus `EN` on an I/O
+/// expander with an I2C error).  When they all share one error type
+/// it's simply `Error<E>`.  The variants for select pins a
+/// multiplexer doesn't have are never returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<S0, S1 = S0, S2 = S0, S3 = S0, S4 = S0, EN = S0> {
+    /// Setting the `S0` (aka "A") select pin failed
+    S0(S0),
+    /// Setting the `S1` (aka "B") select pin failed
+    S1(S1),
+    /// Setting the `S2` (aka "C") select pin failed
+    S2(S2),
+    /// Setting the `S3` (aka "D") select pin failed
+    S3(S3),
+    /// Setting the `S4` (aka "E") select pin failed
+    S4(S4),
+    /// Setting the `EN` (aka "Inhibit") pin failed
+    EN(EN),
 }
 
-/// A 5-pin implementation to support 16-channel multiplexers (e.g. 74HC4067)
-impl<
-        E,
-        S0: OutputPin<Error = E>, // aka "A"
-        S1: OutputPin<Error = E>, // aka "B"
-        S2: OutputPin<Error = E>, // aka "C"
-        S3: OutputPin<Error = E>, // aka "D"
-        EN: OutputPin<Error = E>, // aka "Inhibit"
-    > Output for (S0, S1, S2, S3, EN)
-{
-    /// Sets the current active channel on the multiplexer (0-15)
-    fn set_channel(&mut self, channel: u8) {
-        // NOTE: Figuring out the binary math on this was not fun.  Not fun at all!
-        // Thanks to @grantm11235:matrix.org for showing me the way =)
-        if channel & (1 << 0) == 0 {
-            self.0.set_low().ok();
-        } else {
-            self.0.set_high().ok();
-        }
+/// The error returned by `Multiplexer::new()` if one of the pins
+/// couldn't be set.  The pins are handed back so they can be retried
+/// (e.g. after resetting an I/O expander) or reclaimed.
+pub struct InitError<Pins, E> {
+    /// The pins that were given to `Multiplexer::new()`
+    pub pins: Pins,
+    /// The error returned while setting them (see [`Error`])
+    pub error: E,
+}
 
-        if channel & (1 << 1) == 0 {
-            self.1.set_low().ok();
-        } else {
-            self.1.set_high().ok();
-        }
+impl<Pins, E: fmt::Debug> fmt::Debug for InitError<Pins, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Pins rarely implement `Debug` so only the error is shown
+        f.debug_struct("InitError")
+            .field("error", &self.error)
+            .finish_non_exhaustive()
+    }
+}
 
-        if channel & (1 << 2) == 0 {
-            self.2.set_low().ok();
-        } else {
-            self.2.set_high().ok();
-        }
+/// Errors returned by `Multiplexer::try_set_channel()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelError<E> {
+    /// The channel is out of range for this multiplexer
+    InvalidChannel(u8),
+    /// Setting one of the pins failed (see [`Error`])
+    Pins(E),
+}
 
-        if channel & (1 << 3) == 0 {
-            self.3.set_low().ok();
-        } else {
-            self.3.set_high().ok();
+/// Errors that can occur while reading a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadError<P, A> {
+    /// The channel is out of range for this multiplexer
+    InvalidChannel(u8),
+    /// Selecting the channel failed (see [`Error`])
+    Pins(P),
+    /// The ADC conversion failed
+    Adc(A),
+}
+
+impl<P, A> From<ChannelError<P>> for ReadError<P, A> {
+    fn from(e: ChannelError<P>) -> Self {
+        match e {
+            ChannelError::InvalidChannel(channel) => ReadError::InvalidChannel(channel),
+            ChannelError::Pins(e) => ReadError::Pins(e),
         }
     }
+}
+
+/// The subset of `OutputPin` functionality the multiplexer needs from
+/// its select and enable pins.  It's implemented for every
+/// embedded-hal 1.0 `OutputPin` (`eh1` feature) and for embedded-hal
+/// 0.2 `digital::v2::OutputPin`s wrapped in an [`Eh0Pin`] (`eh0` feature),
+/// so pins from both HAL generations can be mixed in the same tuple.
+pub trait Pin {
+    /// The error returned by the underlying `OutputPin`
+    type Error;
+    /// `false` if the pin isn't really connected to anything (like
+    /// `NoEnable`)
+    const CONNECTED: bool = true;
+    fn set_low(&mut self) -> Result<(), Self::Error>;
+    fn set_high(&mut self) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "eh1")]
+impl<P: eh1::digital::OutputPin> Pin for P {
+    type Error = P::Error;
 
-    /// Brings the `EN` pin low to enable the multiplexer
-    fn enable(&mut self) {
-        self.4.set_low().ok();
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        eh1::digital::OutputPin::set_low(self)
     }
 
-    /// Brings the `EN` pin high to disable the multiplexer
-    fn disable(&mut self) {
-        self.4.set_high().ok();
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        eh1::digital::OutputPin::set_high(self)
     }
+}
 
-    /// Returns the number of channels supported by this multiplexer
-    /// (so you can easily iterate over them).
-    fn num_channels(&self) -> u8 {
-        16
+/// Wraps an embedded-hal 0.2 `digital::v2::OutputPin` so it can be
+/// used with `Multiplexer`, e.g. `(Eh0Pin(s0), Eh0Pin(s1), ...)`.
+///
+/// **NOTE:** A wrapper is needed because a pin type could implement
+/// both the 0.2 and 1.0 `OutputPin` traits (just like `DummyPin` does)
+/// so they can't both be supported directly.
+#[cfg(feature = "eh0")]
+pub struct Eh0Pin<P>(pub P);
+
+#[cfg(feature = "eh0")]
+impl<P: eh0::digital::v2::OutputPin> Pin for Eh0Pin<P> {
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low()
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high()
     }
 }
 
-/// A 4-pin implementation to support 8-channel multiplexers (e.g. 74HC4051)
-impl<
-        E,
-        S0: OutputPin<Error = E>,
-        S1: OutputPin<Error = E>,
-        S2: OutputPin<Error = E>,
-        EN: OutputPin<Error = E>,
-    > Output for (S0, S1, S2, EN)
-{
-    /// Sets the current active channel on the multiplexer (0-7)
-    fn set_channel(&mut self, channel: u8) {
-        if channel & (1 << 0) == 0 {
-            self.0.set_low().ok();
-        } else {
-            self.0.set_high().ok();
-        }
+/// Inverts a pin (`set_high()` drives it low and vice versa) for
+/// boards that drive the multiplexer through inverting level shifters
+/// or parts with an active-high enable, e.g.
+/// `(Inverted(s0), s1, s2, s3, Inverted(en))`.  Since it's applied to
+/// the pin itself every write (including the ones `Multiplexer::new()`
+/// makes) is inverted consistently.
+pub struct Inverted<P>(pub P);
 
-        if channel & (1 << 1) == 0 {
-            self.1.set_low().ok();
-        } else {
-            self.1.set_high().ok();
-        }
+impl<P: Pin> Pin for Inverted<P> {
+    type Error = P::Error;
+    const CONNECTED: bool = P::CONNECTED;
 
-        if channel & (1 << 2) == 0 {
-            self.2.set_low().ok();
-        } else {
-            self.2.set_high().ok();
-        }
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high()
     }
 
-    /// Brings the `EN` pin low to enable the multiplexer
-    fn enable(&mut self) {
-        self.3.set_low().ok();
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low()
     }
+}
+
+/// A trait so we can support 2 to 32-channel multiplexers
+/// simultaneously by merely instantiating them with a tuple of
+/// 1 to 5 select pins followed by the `EN` pin, e.g. a 5
+/// (16-channel) or 4 (8-channel) member tuple of `OutputPin`s
+/// (anything that implements [`Pin`]).  The number of channels
+/// is derived from the number of select pins.
+///
+/// Arrays and slices of select pins intentionally go through
+/// `PortOutput` (`PortOutput::new([s0, s1, s2], en)` or a
+/// `SelectSlice`) instead of having `Output` impls of their own: an
+/// impl for `([S; N], EN)` or `(&mut [S], EN)` would overlap the
+/// `(S0, EN)` tuple impl under coherence rules since embedded-hal
+/// could implement `OutputPin` for arrays or slices.
+pub trait Output {
+    /// The error returned when one of the pins couldn't be set
+    type Error;
+    /// The number of channels supported by this multiplexer or 0 if
+    /// it's only known at runtime (e.g. a `SelectSlice`) in which case
+    /// `num_channels()` has the real number.  Anything that needs the
+    /// number of channels at compile time (e.g. `Cascade`, `Bank`,
+    /// `ChannelMap`, `select()`, and `Snapshot`s) fails to compile if
+    /// it's 0.
+    const NUM_CHANNELS: u8;
+    /// `false` if `enable()`/`disable()` don't actually do anything
+    /// (e.g. the `EN` pin is `NoEnable`)
+    const HAS_ENABLE: bool = true;
+    fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error>;
+    fn enable(&mut self) -> Result<(), Self::Error>;
+    fn disable(&mut self) -> Result<(), Self::Error>;
 
-    /// Brings the `EN` pin high to disable the multiplexer
-    fn disable(&mut self) {
-        self.3.set_high().ok();
+    /// Switches from channel `from` (which the select pins are known to
+    /// be driven to) to channel `to`, only writing the pins whose level
+    /// actually changes.  Defaults to `set_channel(to)`.
+    fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+        let _ = from;
+        self.set_channel(to)
     }
 
     /// Returns the number of channels supported by this multiplexer
     /// (so you can easily iterate over them).
     fn num_channels(&self) -> u8 {
-        8
+        Self::NUM_CHANNELS
+    }
+}
+
+/// Drives the given pin high or low
+fn set_level<P: Pin>(pin: &mut P, high: bool) -> Result<(), P::Error> {
+    if high {
+        pin.set_high()
+    } else {
+        pin.set_low()
     }
 }
 
+/// Implements `Output` for a tuple of select pins (`S0` aka "A",
+/// `S1` aka "B", etc) followed by the `EN` (aka "Inhibit") pin
+macro_rules! impl_output {
+    ($(#[$doc:meta])* $bits:literal: $($s:ident $i:tt),+; $($unused:ident),*; $en:tt) => {
+        $(#[$doc])*
+        impl<$($s: Pin,)+ EN: Pin> Output for ($($s,)+ EN) {
+            // The select pins this multiplexer doesn't have reuse S0's error type
+            type Error = Error<$($s::Error,)+ $($unused::Error,)* EN::Error>;
+            const NUM_CHANNELS: u8 = 1 << $bits;
+            const HAS_ENABLE: bool = EN::CONNECTED;
+
+            /// Sets the current active channel on the multiplexer
+            fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error> {
+                // NOTE: Figuring out the binary math on this was not fun.  Not fun at all!
+                // Thanks to @grantm11235:matrix.org for showing me the way =)
+                $(set_level(&mut self.$i, channel & (1 << $i) != 0).map_err(Error::$s)?;)+
+                Ok(())
+            }
+
+            /// Brings the `EN` pin low to enable the multiplexer
+            fn enable(&mut self) -> Result<(), Self::Error> {
+                self.$en.set_low().map_err(Error::EN)
+            }
+
+            /// Brings the `EN` pin high to disable the multiplexer
+            fn disable(&mut self) -> Result<(), Self::Error> {
+                self.$en.set_high().map_err(Error::EN)
+            }
+
+            /// Only writes the select pins whose level changes
+            fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+                let changed = from ^ to;
+                $(
+                    if changed & (1 << $i) != 0 {
+                        set_level(&mut self.$i, to & (1 << $i) != 0).map_err(Error::$s)?;
+                    }
+                )+
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_output!(
+    /// A 2-pin implementation to support 2-channel switches (e.g. one
+    /// section of a 74HC4053)
+    1: S0 0; S0, S0, S0, S0; 1
+);
+impl_output!(
+    /// A 3-pin implementation to support 4-channel multiplexers (e.g.
+    /// one half of a 74HC4052)
+    2: S0 0, S1 1; S0, S0, S0; 2
+);
+impl_output!(
+    /// A 4-pin implementation to support 8-channel multiplexers (e.g. 74HC4051)
+    3: S0 0, S1 1, S2 2; S0, S0; 3
+);
+impl_output!(
+    /// A 5-pin implementation to support 16-channel multiplexers (e.g. 74HC4067)
+    4: S0 0, S1 1, S2 2, S3 3; S0; 4
+);
+impl_output!(
+    /// A 6-pin implementation to support 32-channel multiplexers (e.g. ADG732)
+    5: S0 0, S1 1, S2 2, S3 3, S4 4; ; 5
+);
+
 impl<Pins: Output> Multiplexer<Pins> {
     /// Given a 5 or 4-member tuple, `(s0, s1, s2, s3, en)` or
     /// `(s0, s1, s2, en)` where every member is an `OutputPin`,
     /// returns a new instance of `Multiplexer` for a
-    /// 16-channel or 8-channel analog multiplexer, respectively.
+    /// 16-channel or 8-channel analog multiplexer, respectively
+    /// (2, 3, and 6-member tuples work too for 2, 4, and
+    /// 32-channel multiplexers).  Returns an [`InitError`] (which
+    /// hands the pins back) if any of the pins couldn't be set.
     ///
     /// **NOTE:** Some multiplexers label S0-S3 as A-D. They're
     /// the same thing.
-    pub fn new(mut pins: Pins) -> Self {
+    pub fn new(mut pins: Pins) -> Result<Self, InitError<Pins, Pins::Error>> {
         // Default to enabled on channel 0
         let active_channel = 0;
         let enabled = true;
-        pins.enable();
-        pins.set_channel(0);
+        if let Err(error) = pins.enable().and_then(|()| pins.set_channel(0)) {
+            return Err(InitError { pins, error });
+        }
         // For quick reference later:
         let num_channels = pins.num_channels();
 
-        Self {
+        Ok(Self {
             pins,
             num_channels,
             active_channel,
             enabled,
+            delay: NoDelay,
+            settle_us: 0,
+            channel_settle_us: [],
+            break_before_make: None,
+            synced: true,
+        })
+    }
+}
+
+impl<Pins: Output, D: Delay, const T: usize> Multiplexer<Pins, D, T> {
+    /// Returns a `Multiplexer` that waits `settle_us` microseconds
+    /// (using the given `delay`) after every channel switch so the
+    /// output can settle before it's sampled.  If the new delay doesn't
+    /// wait (`NoDelay`) the break-before-make guard time is dropped.
+    pub fn with_delay<D2: Delay>(self, delay: D2, settle_us: u32) -> Multiplexer<Pins, D2, T> {
+        Multiplexer {
+            pins: self.pins,
+            num_channels: self.num_channels,
+            active_channel: self.active_channel,
+            enabled: self.enabled,
+            delay,
+            settle_us,
+            channel_settle_us: self.channel_settle_us,
+            break_before_make: self
+                .break_before_make
+                .map(|guard_us| if D2::WAITS { guard_us } else { 0 }),
+            synced: self.synced,
+        }
+    }
+
+    /// Returns a `Multiplexer` that can override `settle_us` for
+    /// channels `0..T2` (see `set_settle_time()`), starting out with
+    /// the given table (e.g. `[None; 16]`).
+    pub fn with_settle_times<const T2: usize>(
+        self,
+        channel_settle_us: [Option<u32>; T2],
+    ) -> Multiplexer<Pins, D, T2> {
+        Multiplexer {
+            pins: self.pins,
+            num_channels: self.num_channels,
+            active_channel: self.active_channel,
+            enabled: self.enabled,
+            delay: self.delay,
+            settle_us: self.settle_us,
+            channel_settle_us,
+            break_before_make: self.break_before_make,
+            synced: self.synced,
+        }
+    }
+
+    /// Overrides the settle time (in microseconds) for the given
+    /// channel.  Use `None` to go back to using `settle_us`.
+    ///
+    /// Returns `ChannelError::InvalidChannel` if the channel is out of
+    /// range for this multiplexer or beyond the table given to
+    /// `with_settle_times()`.
+    pub fn set_settle_time(
+        &mut self,
+        channel: u8,
+        settle_us: Option<u32>,
+    ) -> Result<(), ChannelError<Infallible>> {
+        if channel >= self.num_channels {
+            return Err(ChannelError::InvalidChannel(channel));
+        }
+        match (self.channel_settle_us.get_mut(channel as usize), settle_us) {
+            (Some(slot), _) => *slot = settle_us,
+            // Nothing to clear
+            (None, None) => {}
+            (None, Some(_)) => return Err(ChannelError::InvalidChannel(channel)),
+        }
+        Ok(())
+    }
+
+    /// Returns how long (in microseconds) to wait after switching
+    /// to the given channel
+    pub fn settle_time(&self, channel: u8) -> u32 {
+        self.channel_settle_us
+            .get(channel as usize)
+            .copied()
+            .flatten()
+            .unwrap_or(self.settle_us)
+    }
+
+    /// Returns the break-before-make guard time (in microseconds) or
+    /// `None` if break-before-make switching is off
+    pub fn break_before_make(&self) -> Option<u32> {
+        self.break_before_make
+    }
+
+    /// Turns break-before-make switching on (without a guard time) or
+    /// off.  While it's on the multiplexer is disabled while the select
+    /// pins change so intermediate channels never get connected.  It's
+    /// skipped if there's no `EN` pin.
+    pub fn set_break_before_make(&mut self, enabled: bool) {
+        self.break_before_make = if enabled { Some(0) } else { None };
+    }
+
+    /// Turns break-before-make switching on and waits `guard_us`
+    /// microseconds (using the delay given to `with_delay()`) before
+    /// re-enabling the multiplexer.  Calling this on a `Multiplexer`
+    /// without a delay (`NoDelay`) fails to compile.
+    pub fn set_break_before_make_guard(&mut self, guard_us: u32) {
+        #[allow(clippy::let_unit_value)]
+        let () = DelayWaits::<D>::OK;
+        self.break_before_make = Some(guard_us);
+    }
+
+    /// Waits for the output to settle on the given channel
+    fn settle(&mut self, channel: u8) {
+        let settle_us = self.settle_time(channel);
+        if settle_us > 0 {
+            self.delay.delay_us(settle_us);
         }
     }
 
     /// Sets the current active channel on the multiplexer
     /// (0 up to `num_channels`) and records that state in
-    /// `self.active_channel`
-    pub fn set_channel(&mut self, channel: u8) {
-        self.pins.set_channel(channel);
+    /// `self.active_channel` (only if all the select pins
+    /// could be set).  If the channel changed it then waits
+    /// for the output to settle (see `with_delay()`), as it also does
+    /// after a failed write left the select pins in an unknown state.
+    ///
+    /// The levels the select pins were last driven to are tracked so
+    /// only the ones that change get written (setting the active
+    /// channel again writes nothing at all).  If setting a pin fails
+    /// every pin gets written next time (see also `resync()`).
+    ///
+    /// With break-before-make switching on (and an `EN` pin) the
+    /// multiplexer is disabled while the select pins change so
+    /// intermediate channels never get connected.  If setting a select pin fails
+    /// it's left disabled (`self.enabled = false`).
+    ///
+    /// **NOTE:** The channel isn't bounds-checked (out-of-range
+    /// channels get their high bits ignored by the hardware).  Use
+    /// `try_set_channel()` or `select()` if that matters.
+    pub fn set_channel(&mut self, channel: u8) -> Result<(), Pins::Error> {
+        let switched = self.switching(channel);
+        let guard_us = self
+            .break_before_make
+            .filter(|_| Pins::HAS_ENABLE && self.enabled && switched);
+        if guard_us.is_some() {
+            self.pins.disable()?;
+            self.enabled = false;
+        }
+        let result = if self.synced {
+            self.pins.switch_channel(self.active_channel, channel)
+        } else {
+            self.pins.set_channel(channel)
+        };
+        self.synced = result.is_ok();
+        result?;
         self.active_channel = channel;
+        if let Some(guard_us) = guard_us {
+            if guard_us > 0 {
+                self.delay.delay_us(guard_us);
+            }
+            self.pins.enable()?;
+            self.enabled = true;
+        }
+        if switched {
+            self.settle(channel);
+        }
+        Ok(())
     }
 
-    /// Enables the multiplexer and sets `self.enabled = true`
-    pub fn enable(&mut self) {
-        self.pins.enable();
+    /// Returns `true` if selecting `channel` may change which channel is
+    /// connected: it isn't the active channel or a failed write left the
+    /// select pins in an unknown state (so the output has to settle)
+    pub(crate) fn switching(&self, channel: u8) -> bool {
+        channel != self.active_channel || !self.synced
+    }
+
+    /// Drives every pin to the state this `Multiplexer` has recorded
+    /// (`enabled` then `active_channel`) whether they seem to need it or
+    /// not.  Use it when the pins' state is unknown, e.g. after a
+    /// brown-out reset an I/O expander or after using `pins` directly.
+    /// If it fails every pin gets written on the next `set_channel()`.
+    pub fn resync(&mut self) -> Result<(), Pins::Error> {
+        self.synced = false;
+        if self.enabled {
+            self.pins.enable()?;
+        } else {
+            self.pins.disable()?;
+        }
+        self.pins.set_channel(self.active_channel)?;
+        self.synced = true;
+        Ok(())
+    }
+
+    /// Same as `set_channel()` but returns
+    /// `ChannelError::InvalidChannel` (without touching any pins)
+    /// if `channel` is out of range for this multiplexer
+    pub fn try_set_channel(&mut self, channel: u8) -> Result<(), ChannelError<Pins::Error>> {
+        if channel >= self.num_channels {
+            return Err(ChannelError::InvalidChannel(channel));
+        }
+        self.set_channel(channel).map_err(ChannelError::Pins)
+    }
+
+    /// Sets the current active channel using a `Channel<N>` (which
+    /// is always in range).  `N` must match the multiplexer's number
+    /// of channels; using a `Channel<16>` with an 8-channel
+    /// multiplexer (or vice versa) fails to compile.
+    pub fn select<const N: u8>(&mut self, channel: Channel<N>) -> Result<(), Pins::Error> {
+        #[allow(clippy::let_unit_value)]
+        let () = SameNumChannels::<Pins, N>::OK;
+        self.set_channel(channel.index())
+    }
+
+    /// Enables the multiplexer and sets `self.enabled = true`.
+    /// If it was disabled it then waits for the output to settle.
+    pub fn enable(&mut self) -> Result<(), Pins::Error> {
+        self.pins.enable()?;
+        let switched = !self.enabled;
         self.enabled = true;
+        if switched {
+            self.settle(self.active_channel);
+        }
+        Ok(())
     }
 
     /// Disables the multiplexer and sets `self.enabled = false`
-    pub fn disable(&mut self) {
-        self.pins.enable();
+    pub fn disable(&mut self) -> Result<(), Pins::Error> {
+        self.pins.disable()?;
         self.enabled = false;
+        Ok(())
     }
 }
 
-/// A DummyPin for when you've got your EN (enable) pin run to GND
-/// (the analog multiplexer is always enabled)
+/// Compile-time check that a `Delay` actually waits
+struct DelayWaits<D>(PhantomData<D>);
+
+impl<D: Delay> DelayWaits<D> {
+    const OK: () = assert!(
+        D::WAITS,
+        "a break-before-make guard time needs a delay (see with_delay())"
+    );
+}
+
+/// Compile-time check that a `Channel<N>` matches an `Output`
+struct SameNumChannels<Pins, const N: u8>(PhantomData<Pins>);
+
+impl<Pins: Output, const N: u8> SameNumChannels<Pins, N> {
+    const OK: () = assert!(
+        N == Pins::NUM_CHANNELS && N != 0,
+        "Channel<N> doesn't match the multiplexer's number of channels"
+    );
+}
+
+/// A pin that ignores every write.  It implements both the
+/// embedded-hal 0.2 and 1.0 `OutputPin` traits so it can stand in for
+/// any unused output pin.
+///
+/// **NOTE:** The multiplexer treats it like a real `EN` pin so
+/// break-before-make switching is *not* skipped (the guard time is
+/// still waited out).  If `EN` is tied to GND use `NoEnable` instead.
 pub struct DummyPin;
-impl OutputPin for DummyPin {
+
+#[cfg(feature = "eh0")]
+impl eh0::digital::v2::OutputPin for DummyPin {
+    type Error = Infallible;
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl eh1::digital::ErrorType for DummyPin {
+    type Error = Infallible;
+}
+
+#[cfg(feature = "eh1")]
+impl eh1::digital::OutputPin for DummyPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+// Without embedded-hal 1.0 the blanket `Pin` impl isn't there to cover us
+#[cfg(not(feature = "eh1"))]
+impl Pin for DummyPin {
+    type Error = Infallible;
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Takes the place of the `EN` pin when the multiplexer doesn't have
+/// one, e.g. `(s0, s1, s2, NoEnable)`.  Unlike `DummyPin` the
+/// multiplexer knows there's nothing there so anything that relies on
+/// `EN` (like `break_before_make`) is skipped.
+pub struct NoEnable;
+
+impl Pin for NoEnable {
     type Error = Infallible;
+    const CONNECTED: bool = false;
     fn set_low(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
@@ -0,0 +1,418 @@
+//! Recording mock pins for testing code built on `Multiplexer` on the
+//! host (enable the `mock` feature).
+//!
+//! A `MockMux` hands out `MockPin`s that record every level they're
+//! driven to (with a sequence number shared by all the pins) and keeps
+//! track of which channel is electrically selected at any given time:
+//!
+//! ```
+//! use analog_multiplexer::mock::MockMux;
+//! use analog_multiplexer::Multiplexer;
+//!
+//! let mock = MockMux::new();
+//! let mut multiplexer = Multiplexer::new(mock.pins16()).unwrap();
+//! multiplexer.set_channel(5).unwrap();
+//! let seq = mock.seq(); // e.g. when the ADC read happened
+//! multiplexer.disable().unwrap();
+//! assert_eq!(mock.selected_at(seq), Some(5));
+//! assert_eq!(mock.selected(), None); // Disabled
+//! ```
+//!
+//! With the `eh0` feature (or `async`) it can also hand out a `MockAdc`
+//! that reads back a value derived from the selected channel.
+
+#[cfg(any(feature = "eh0", feature = "async"))]
+use core::convert::Infallible;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec::Vec;
+
+/// Identifies one of the multiplexer's pins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Line {
+    /// A channel select pin (`Select(0)` is `S0`, etc)
+    Select(u8),
+    /// The `EN` (aka "Inhibit") pin
+    Enable,
+}
+
+/// A single recorded pin write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    /// Increases by one with every write to any of the `MockMux`'s pins
+    pub seq: u32,
+    pub line: Line,
+    /// `true` if the pin was set high
+    pub high: bool,
+}
+
+/// The most select pins a `MockMux` can hand out
+const MAX_SELECT: usize = 8;
+
+/// The level every line is driven to (`None` if it hasn't been yet)
+#[derive(Clone, Copy, Default)]
+struct Levels {
+    select: [Option<bool>; MAX_SELECT],
+    enable: Option<bool>,
+}
+
+impl Levels {
+    fn get_mut(&mut self, line: Line) -> &mut Option<bool> {
+        match line {
+            Line::Select(bit) => &mut self.select[bit as usize],
+            Line::Enable => &mut self.enable,
+        }
+    }
+
+    fn get(mut self, line: Line) -> Option<bool> {
+        *self.get_mut(line)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    seq: u32,
+    num_select: u8,
+    has_enable: bool,
+    log: Vec<Transition>,
+    /// The present level of every line (`clear()` doesn't touch it)
+    levels: Levels,
+    /// The levels as of the last `clear()` (for looking up history)
+    cleared: Levels,
+    failing: Vec<Line>,
+}
+
+/// A mock multiplexer that hands out `MockPin`s and records what
+/// they were driven to
+#[derive(Clone, Default)]
+pub struct MockMux {
+    state: Rc<RefCell<State>>,
+}
+
+impl MockMux {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a `MockPin` for the given line
+    pub fn pin(&self, line: Line) -> MockPin {
+        let mut state = self.state.borrow_mut();
+        match line {
+            Line::Select(bit) => {
+                assert!((bit as usize) < MAX_SELECT, "too many select pins");
+                state.num_select = state.num_select.max(bit + 1);
+            }
+            Line::Enable => state.has_enable = true,
+        }
+        MockPin {
+            line,
+            state: self.state.clone(),
+        }
+    }
+
+    /// Returns pins for a 16-channel multiplexer: `(s0, s1, s2, s3, en)`
+    pub fn pins16(&self) -> (MockPin, MockPin, MockPin, MockPin, MockPin) {
+        (
+            self.pin(Line::Select(0)),
+            self.pin(Line::Select(1)),
+            self.pin(Line::Select(2)),
+            self.pin(Line::Select(3)),
+            self.pin(Line::Enable),
+        )
+    }
+
+    /// Returns pins for an 8-channel multiplexer: `(s0, s1, s2, en)`
+    pub fn pins8(&self) -> (MockPin, MockPin, MockPin, MockPin) {
+        (
+            self.pin(Line::Select(0)),
+            self.pin(Line::Select(1)),
+            self.pin(Line::Select(2)),
+            self.pin(Line::Enable),
+        )
+    }
+
+    /// Returns a `MockAdc` that reads back `scale` times whichever
+    /// channel is electrically selected
+    #[cfg(any(feature = "eh0", feature = "async"))]
+    pub fn adc(&self, scale: u16) -> MockAdc {
+        MockAdc {
+            mock: self.clone(),
+            scale,
+            two_step: false,
+            started: None,
+        }
+    }
+
+    /// Returns the sequence number of the most recent pin write
+    /// (0 if nothing's been written yet)
+    pub fn seq(&self) -> u32 {
+        self.state.borrow().seq
+    }
+
+    /// Returns every pin write recorded so far (in order)
+    pub fn transitions(&self) -> Vec<Transition> {
+        self.state.borrow().log.clone()
+    }
+
+    /// Returns the line and level of every pin write recorded so far
+    /// then `clear()`s them
+    pub fn take_levels(&self) -> Vec<(Line, bool)> {
+        let levels = self
+            .transitions()
+            .iter()
+            .map(|t| (t.line, t.high))
+            .collect();
+        self.clear();
+        levels
+    }
+
+    /// Forgets every pin write recorded so far (the sequence number
+    /// keeps counting up).  The levels the pins are driven to (and so
+    /// the selected channel) are remembered.
+    pub fn clear(&self) {
+        let mut state = self.state.borrow_mut();
+        state.cleared = state.levels;
+        state.log.clear();
+    }
+
+    /// Makes writes to the given line fail (or succeed again)
+    pub fn set_failing(&self, line: Line, failing: bool) {
+        let mut state = self.state.borrow_mut();
+        state.failing.retain(|l| *l != line);
+        if failing {
+            state.failing.push(line);
+        }
+    }
+
+    /// Returns the level the given line is presently driven to
+    /// (`None` if it hasn't been driven yet)
+    pub fn level(&self, line: Line) -> Option<bool> {
+        self.state.borrow().levels.get(line)
+    }
+
+    /// Returns the level the given line was driven to as of the given
+    /// sequence number (`None` if it hadn't been driven yet).  Writes
+    /// from before the last `clear()` are only known by the level they
+    /// left the line at.
+    pub fn level_at(&self, line: Line, seq: u32) -> Option<bool> {
+        let state = self.state.borrow();
+        match state
+            .log
+            .iter()
+            .rev()
+            .find(|t| t.line == line && t.seq <= seq)
+        {
+            Some(t) => Some(t.high),
+            None => state.cleared.get(line),
+        }
+    }
+
+    /// Returns the channel that's presently electrically selected
+    /// (`None` if the multiplexer is disabled or not all of the
+    /// select pins have been driven yet)
+    pub fn selected(&self) -> Option<u8> {
+        self.selected_at(u32::MAX)
+    }
+
+    /// Returns the channel that was electrically selected as of the
+    /// given sequence number (see `selected()`)
+    pub fn selected_at(&self, seq: u32) -> Option<u8> {
+        let (num_select, has_enable) = {
+            let state = self.state.borrow();
+            (state.num_select, state.has_enable)
+        };
+        // EN is active low (no EN pin means it's run to GND)
+        if has_enable && self.level_at(Line::Enable, seq) != Some(false) {
+            return None;
+        }
+        let mut channel = 0;
+        for bit in 0..num_select {
+            if self.level_at(Line::Select(bit), seq)? {
+                channel |= 1 << bit;
+            }
+        }
+        Some(channel)
+    }
+}
+
+/// The error returned by a `MockPin` that was told to fail (see
+/// `MockMux::set_failing()`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockError(pub Line);
+
+/// A pin handed out by a `MockMux`.  It implements the embedded-hal
+/// 1.0 (and 0.2 `digital::v2`) `OutputPin` traits.
+pub struct MockPin {
+    line: Line,
+    state: Rc<RefCell<State>>,
+}
+
+impl MockPin {
+    pub fn line(&self) -> Line {
+        self.line
+    }
+
+    fn set(&mut self, high: bool) -> Result<(), MockError> {
+        let mut state = self.state.borrow_mut();
+        if state.failing.contains(&self.line) {
+            return Err(MockError(self.line));
+        }
+        state.seq += 1;
+        let seq = state.seq;
+        state.log.push(Transition {
+            seq,
+            line: self.line,
+            high,
+        });
+        *state.levels.get_mut(self.line) = Some(high);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl eh1::digital::Error for MockError {
+    fn kind(&self) -> eh1::digital::ErrorKind {
+        eh1::digital::ErrorKind::Other
+    }
+}
+
+#[cfg(feature = "eh1")]
+impl eh1::digital::ErrorType for MockPin {
+    type Error = MockError;
+}
+
+#[cfg(feature = "eh1")]
+impl eh1::digital::OutputPin for MockPin {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true)
+    }
+}
+
+#[cfg(feature = "eh0")]
+impl eh0::digital::v2::OutputPin for MockPin {
+    type Error = MockError;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true)
+    }
+}
+
+// Without embedded-hal 1.0 the blanket `Pin` impl isn't there to cover us
+#[cfg(not(feature = "eh1"))]
+impl crate::Pin for MockPin {
+    type Error = MockError;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.set(false)
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.set(true)
+    }
+}
+
+/// A mock ADC (see `MockMux::adc()`).  Reading `MockZ<N>` returns
+/// `1000 * N + scale * channel` where the channel is whichever one is
+/// electrically selected.
+///
+/// **NOTE:** Reading it while no channel is selected panics.
+#[cfg(any(feature = "eh0", feature = "async"))]
+pub struct MockAdc {
+    mock: MockMux,
+    scale: u16,
+    two_step: bool,
+    /// The channel whose (two-step) conversion is in progress
+    started: Option<u8>,
+}
+
+#[cfg(any(feature = "eh0", feature = "async"))]
+impl MockAdc {
+    /// What a two-step conversion reads back if the selected channel
+    /// changed before it completed
+    pub const MIXED: u16 = u16::MAX;
+
+    /// Makes every conversion take two reads: the first one starts it
+    /// (returning `nb::Error::WouldBlock` with the `eh0` `OneShot`) and
+    /// the second one completes it (returning `MIXED` if the selected
+    /// channel changed in the meantime)
+    pub fn two_step(mut self) -> Self {
+        self.two_step = true;
+        self
+    }
+
+    /// Returns `None` while a two-step conversion is in progress
+    fn convert(&mut self, offset: u16) -> Option<u16> {
+        let selected = self
+            .mock
+            .selected()
+            .expect("MockAdc read while no channel was selected");
+        let channel = if self.two_step {
+            match self.started.take() {
+                None => {
+                    self.started = Some(selected);
+                    return None;
+                }
+                Some(started) if started == selected => selected,
+                Some(_) => return Some(Self::MIXED),
+            }
+        } else {
+            selected
+        };
+        Some(1000 * offset + self.scale * channel as u16)
+    }
+}
+
+/// An analog pin read by a `MockAdc` (`MockZ<0>` unless several are
+/// needed, e.g. the banks of a `ParallelMultiplexer`)
+#[cfg(any(feature = "eh0", feature = "async"))]
+pub struct MockZ<const N: u16>;
+
+#[cfg(feature = "eh0")]
+impl<const N: u16> eh0::adc::Channel<MockAdc> for MockZ<N> {
+    type ID = u16;
+
+    fn channel() -> u16 {
+        N
+    }
+}
+
+#[cfg(feature = "eh0")]
+impl<const N: u16> eh0::adc::OneShot<MockAdc, u16, MockZ<N>> for MockAdc {
+    type Error = Infallible;
+
+    fn read(&mut self, _pin: &mut MockZ<N>) -> nb::Result<u16, Self::Error> {
+        self.convert(N).ok_or(nb::Error::WouldBlock)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<const N: u16> crate::AsyncAdc<MockZ<N>> for MockAdc {
+    type Error = Infallible;
+
+    /// Two-step conversions take two polls
+    async fn read(&mut self, _pin: &mut MockZ<N>) -> Result<u16, Self::Error> {
+        loop {
+            if let Some(value) = self.convert(N) {
+                return Ok(value);
+            }
+            let mut yielded = false;
+            core::future::poll_fn(|_| {
+                if yielded {
+                    core::task::Poll::Ready(())
+                } else {
+                    yielded = true;
+                    core::task::Poll::Pending
+                }
+            })
+            .await
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! The order channels get visited in while scanning.
+
+/// The order to scan channels in.  Whatever the order, values are
+/// always stored by channel (so `snapshot[5]` is channel 5).
+///
+/// Scanning in ascending order switches every select line at once on
+/// the 7 → 8 and 15 → 0 transitions (briefly selecting channels that
+/// weren't asked for along the way).  In `Gray` code order only one
+/// select line changes at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanOrder<'a> {
+    /// Channels `0, 1, 2, ...`
+    #[default]
+    Ascending,
+    /// Channels `..., 2, 1, 0`
+    Descending,
+    /// Channels `0, 1, 3, 2, 6, 7, 5, 4, ...` (only one select line
+    /// changes between consecutive channels)
+    Gray,
+    /// Exactly the given channels in the given order (e.g. a
+    /// permutation or a subset of the channels)
+    Custom(&'a [u8]),
+}
+
+impl<'a> ScanOrder<'a> {
+    /// Returns an iterator over the channels of a `num_channels`-channel
+    /// multiplexer in this order
+    pub fn channels(self, num_channels: u8) -> Channels<'a> {
+        Channels {
+            order: self,
+            num_channels,
+            step: 0,
+        }
+    }
+}
+
+/// An iterator over channels in a `ScanOrder` (see `ScanOrder::channels()`)
+#[derive(Debug, Clone)]
+pub struct Channels<'a> {
+    order: ScanOrder<'a>,
+    num_channels: u8,
+    step: u16,
+}
+
+impl<'a> Iterator for Channels<'a> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        let num_channels = self.num_channels as u16;
+        loop {
+            let step = self.step;
+            self.step += 1;
+            return match self.order {
+                ScanOrder::Ascending if step < num_channels => Some(step as u8),
+                ScanOrder::Descending if step < num_channels => {
+                    Some((num_channels - 1 - step) as u8)
+                }
+                ScanOrder::Gray if step < num_channels.next_power_of_two() => {
+                    let channel = step ^ (step >> 1);
+                    if channel >= num_channels {
+                        continue; // Not a power of two (e.g. a `Cascade`)
+                    }
+                    Some(channel as u8)
+                }
+                ScanOrder::Custom(channels) => channels.get(step as usize).copied(),
+                _ => {
+                    self.step = step; // Stay exhausted
+                    None
+                }
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn channel_orders() {
+        let ascending: Vec<u8> = ScanOrder::Ascending.channels(4).collect();
+        assert_eq!(ascending, [0, 1, 2, 3]);
+        let descending: Vec<u8> = ScanOrder::Descending.channels(4).collect();
+        assert_eq!(descending, [3, 2, 1, 0]);
+        let gray: Vec<u8> = ScanOrder::Gray.channels(8).collect();
+        assert_eq!(gray, [0, 1, 3, 2, 6, 7, 5, 4]);
+        let gray: Vec<u8> = ScanOrder::Gray.channels(6).collect();
+        assert_eq!(gray, [0, 1, 3, 2, 5, 4]);
+        let custom: Vec<u8> = ScanOrder::Custom(&[7, 3]).channels(8).collect();
+        assert_eq!(custom, [7, 3]);
+    }
+
+    #[test]
+    fn gray_order_changes_one_bit_at_a_time() {
+        let gray: Vec<u8> = ScanOrder::Gray.channels(16).collect();
+        assert_eq!(gray.len(), 16);
+        for pair in gray.windows(2).chain([[gray[15], gray[0]].as_slice()]) {
+            assert_eq!((pair[0] ^ pair[1]).count_ones(), 1);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+//! Parallel banks of multiplexers that share their select lines but
+//! each feed a separate ADC input.
+//!
+//! With `B` multiplexers wired this way one select change yields `B`
+//! samples, so scanning 64 hall-effect keys on four 74HC4067s only
+//! takes 16 channel switches:
+//!
+//! ```ignore
+//! let mut multiplexer = Multiplexer::new(pins)
+//!     .unwrap()
+//!     .with_banks((adc1, (pa0, pa1, pa2, pa3)));
+//! let values: [u16; 4] = multiplexer.read_channel(5).unwrap(); // Channel 5 of every bank
+//! let snapshot: [Snapshot<16>; 4] = multiplexer.scan().unwrap(); // snapshot[bank][channel]
+//! ```
+
+use eh0::adc::{Channel, OneShot};
+
+use crate::snapshot::SnapshotSize;
+use crate::{Delay, Multiplexer, NoDelay, Output, ReadError, ScanOrder, Snapshot};
+
+/// The ADC inputs connected to the common (`Z`) pins of `B` banks of
+/// multiplexers.  It's implemented for:
+///
+/// * `[(adc, pin); B]`: A separate ADC (and analog pin) per bank.
+/// * `(adc, [pin; B])`: One ADC and `B` analog pins of the same type.
+/// * `(adc, (pin0, pin1, ...))`: One ADC and 2-8 analog pins of
+///   different types.
+///
+/// Implement it yourself for any other wiring.
+pub trait BankInputs<ADC, const B: usize> {
+    /// The error returned if a conversion fails
+    type Error;
+    /// Reads the ADC input connected to the given bank (`0..B`)
+    fn read(&mut self, bank: usize) -> nb::Result<u16, Self::Error>;
+}
+
+impl<ADC, Adc, Z, const B: usize> BankInputs<ADC, B> for [(Adc, Z); B]
+where
+    Adc: OneShot<ADC, u16, Z>,
+    Z: Channel<ADC>,
+{
+    type Error = Adc::Error;
+
+    fn read(&mut self, bank: usize) -> nb::Result<u16, Self::Error> {
+        let (adc, pin) = &mut self[bank];
+        adc.read(pin)
+    }
+}
+
+impl<ADC, Adc, Z, const B: usize> BankInputs<ADC, B> for (Adc, [Z; B])
+where
+    Adc: OneShot<ADC, u16, Z>,
+    Z: Channel<ADC>,
+{
+    type Error = Adc::Error;
+
+    fn read(&mut self, bank: usize) -> nb::Result<u16, Self::Error> {
+        self.0.read(&mut self.1[bank])
+    }
+}
+
+macro_rules! impl_bank_inputs {
+    ($b:literal: $($z:ident $i:tt),+) => {
+        impl<ADC, Adc, E, $($z),+> BankInputs<ADC, $b> for (Adc, ($($z,)+))
+        where
+            $(Adc: OneShot<ADC, u16, $z, Error = E>, $z: Channel<ADC>,)+
+        {
+            type Error = E;
+
+            fn read(&mut self, bank: usize) -> nb::Result<u16, E> {
+                match bank {
+                    $($i => self.0.read(&mut (self.1).$i),)+
+                    _ => panic!("bank {} is out of range", bank),
+                }
+            }
+        }
+    };
+}
+
+impl_bank_inputs!(2: Z0 0, Z1 1);
+impl_bank_inputs!(3: Z0 0, Z1 1, Z2 2);
+impl_bank_inputs!(4: Z0 0, Z1 1, Z2 2, Z3 3);
+impl_bank_inputs!(5: Z0 0, Z1 1, Z2 2, Z3 3, Z4 4);
+impl_bank_inputs!(6: Z0 0, Z1 1, Z2 2, Z3 3, Z4 4, Z5 5);
+impl_bank_inputs!(7: Z0 0, Z1 1, Z2 2, Z3 3, Z4 4, Z5 5, Z6 6);
+impl_bank_inputs!(8: Z0 0, Z1 1, Z2 2, Z3 3, Z4 4, Z5 5, Z6 6, Z7 7);
+
+/// A `Multiplexer` driving the shared select lines of several banks
+/// along with the ADC inputs connected to each bank's common (`Z`)
+/// pin (see [`BankInputs`]).  Any settling delay configured on the
+/// `Multiplexer` is applied once per channel switch.
+pub struct ParallelMultiplexer<Pins, Inputs, D = NoDelay, const T: usize = 0> {
+    pub multiplexer: Multiplexer<Pins, D, T>,
+    pub inputs: Inputs,
+}
+
+impl<Pins: Output, Inputs, D: Delay, const T: usize> ParallelMultiplexer<Pins, Inputs, D, T> {
+    pub fn new(multiplexer: Multiplexer<Pins, D, T>, inputs: Inputs) -> Self {
+        Self {
+            multiplexer,
+            inputs,
+        }
+    }
+
+    /// Selects the given channel then reads it on every bank (in bank
+    /// order).  Returns `ReadError::InvalidChannel` if the channel is
+    /// out of range for this multiplexer.
+    pub fn read_channel<ADC, const B: usize>(
+        &mut self,
+        channel: u8,
+    ) -> Result<[u16; B], ReadError<Pins::Error, Inputs::Error>>
+    where
+        Inputs: BankInputs<ADC, B>,
+    {
+        self.multiplexer.try_set_channel(channel)?;
+        let mut values = [0; B];
+        for (bank, value) in values.iter_mut().enumerate() {
+            *value = nb::block!(self.inputs.read(bank)).map_err(ReadError::Adc)?;
+        }
+        Ok(values)
+    }
+
+    /// Reads every channel of every bank and returns a `Snapshot` per
+    /// bank (so `snapshot[bank][channel]`).  `N` must match the
+    /// multiplexer's number of channels; a mismatch fails to compile.
+    pub fn scan<ADC, const B: usize, const N: usize>(
+        &mut self,
+    ) -> Result<[Snapshot<N>; B], ReadError<Pins::Error, Inputs::Error>>
+    where
+        Inputs: BankInputs<ADC, B>,
+    {
+        self.scan_ordered(ScanOrder::Ascending)
+    }
+
+    /// Same as `scan()` but visits the channels in the given order.
+    /// Channels that aren't part of the order read as 0.
+    pub fn scan_ordered<ADC, const B: usize, const N: usize>(
+        &mut self,
+        order: ScanOrder,
+    ) -> Result<[Snapshot<N>; B], ReadError<Pins::Error, Inputs::Error>>
+    where
+        Inputs: BankInputs<ADC, B>,
+    {
+        #[allow(clippy::let_unit_value)]
+        let () = SnapshotSize::<Pins, N>::OK;
+        let mut snapshots = [Snapshot::new(); B];
+        for chan in order.channels(self.multiplexer.num_channels) {
+            let values = self.read_channel(chan)?;
+            for (snapshot, value) in snapshots.iter_mut().zip(values) {
+                snapshot[chan] = value;
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Returns the `Multiplexer` and the bank inputs
+    pub fn release(self) -> (Multiplexer<Pins, D, T>, Inputs) {
+        (self.multiplexer, self.inputs)
+    }
+}
+
+impl<Pins: Output, D: Delay, const T: usize> Multiplexer<Pins, D, T> {
+    /// Turns this `Multiplexer` into a `ParallelMultiplexer` that owns
+    /// the ADC inputs connected to every bank's common (`Z`) pin.
+    pub fn with_banks<Inputs>(self, inputs: Inputs) -> ParallelMultiplexer<Pins, Inputs, D, T> {
+        ParallelMultiplexer::new(self, inputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Line, MockMux, MockZ};
+
+    #[test]
+    fn scan_switches_channels_once_per_channel() {
+        let mock = MockMux::new();
+        // Reads back `1000 * bank + 10 * channel`
+        let adc = mock.adc(10);
+        let mut multiplexer = Multiplexer::new(mock.pins16())
+            .unwrap()
+            .with_banks((adc, (MockZ::<0>, MockZ::<1>, MockZ::<2>, MockZ::<3>)));
+        let seq = mock.seq();
+        let snapshots: [Snapshot<16>; 4] = multiplexer.scan().unwrap();
+        for (bank, snapshot) in snapshots.iter().enumerate() {
+            for (chan, value) in snapshot {
+                assert_eq!(value, 1000 * bank as u16 + 10 * chan as u16);
+            }
+        }
+        // 64 values for 15 channel switches (channel 0 was already selected)
+        let s0_writes = mock
+            .transitions()
+            .iter()
+            .filter(|t| t.seq > seq && t.line == Line::Select(0))
+            .count();
+        assert_eq!(s0_writes, 15);
+    }
+
+    #[test]
+    fn read_channel_reads_every_bank() {
+        let mock = MockMux::new();
+        let inputs = [(mock.adc(10), MockZ::<0>), (mock.adc(10), MockZ::<0>)];
+        let mut multiplexer = Multiplexer::new(mock.pins8()).unwrap().with_banks(inputs);
+        assert_eq!(multiplexer.read_channel(3), Ok([30, 30]));
+        assert_eq!(
+            multiplexer.read_channel::<_, 2>(8),
+            Err(ReadError::InvalidChannel(8))
+        );
+        let mut multiplexer = Multiplexer::new(mock.pins8())
+            .unwrap()
+            .with_banks((mock.adc(10), [MockZ::<2>, MockZ::<2>, MockZ::<2>]));
+        assert_eq!(multiplexer.read_channel(7), Ok([2070, 2070, 2070]));
+    }
+}
@@ -0,0 +1,301 @@
+//! Updating every select line at once through a GPIO port (or an I/O
+//! expander's output latch).
+//!
+//! Setting `S0`-`S3` one pin at a time means the multiplexer briefly
+//! selects intermediate channels on the way to the new one.  Implement
+//! `SelectPort` for something that can write several lines in a single
+//! operation (e.g. a GPIO port's `BSRR` register) and wrap it in a
+//! `PortOutput` to use it in place of a pin tuple:
+//!
+//! ```ignore
+//! struct PortB; // S0-S3 on PB12-PB15
+//!
+//! impl SelectPort for PortB {
+//!     type Error = Infallible;
+//!     const BITS: u8 = 4;
+//!
+//!     fn write_bits(&mut self, value: u8, mask: u8) -> Result<(), Infallible> {
+//!         let set = (value & mask) as u32;
+//!         let reset = (!value & mask) as u32;
+//!         unsafe { (*GPIOB::ptr()).bsrr.write(|w| w.bits((reset << 28) | (set << 12))) };
+//!         Ok(())
+//!     }
+//! }
+//!
+//! let mut multiplexer = Multiplexer::new(PortOutput::new(PortB, en)).unwrap();
+//! ```
+
+use crate::{Error, NoEnable, Output, Pin};
+
+/// Something that can drive all of a multiplexer's select lines in a
+/// single write.  Bit `n` of `value` is the level of `Sn`.
+///
+/// It's also implemented for tuples of 1 to 5 individual pins (e.g.
+/// `(s0, s1, s2, s3)`) and arrays of them (e.g. `[s0, s1, s2, s3]` of
+/// type-erased pins) which are written one at a time (just like the
+/// `Output` tuple implementations).  `PortOutput::without_enable()`
+/// turns those into an `Output` for multiplexers without an `EN` pin.
+/// For select pins whose number is only known at runtime see
+/// `SelectSlice`.
+pub trait SelectPort {
+    /// The error returned if the write fails
+    type Error;
+    /// The number of select lines (so the multiplexer has
+    /// `1 << BITS` channels) or 0 if it's only known at runtime
+    const BITS: u8;
+    /// Drives every select line whose bit is set in `mask` to the
+    /// level of that bit in `value` (leaving the others alone)
+    fn write_bits(&mut self, value: u8, mask: u8) -> Result<(), Self::Error>;
+
+    /// Returns the number of select lines.  Only needs implementing if
+    /// `BITS` is 0.
+    fn bits(&self) -> u8 {
+        Self::BITS
+    }
+}
+
+/// Drives the given pin to the given bit of `value` if it's in `mask`
+fn write_bit<P: Pin>(pin: &mut P, bit: u8, value: u8, mask: u8) -> Result<(), P::Error> {
+    if mask & (1 << bit) == 0 {
+        Ok(())
+    } else if value & (1 << bit) == 0 {
+        pin.set_low()
+    } else {
+        pin.set_high()
+    }
+}
+
+/// Implements `SelectPort` for a tuple of individual select pins
+macro_rules! impl_select_port {
+    ($bits:literal: $($s:ident $i:tt),+; $($unused:ident),*) => {
+        impl<$($s: Pin),+> SelectPort for ($($s,)+) {
+            // The select pins (and `EN`) it doesn't have reuse S0's error type
+            type Error = Error<$($s::Error,)+ $($unused::Error,)* S0::Error>;
+            const BITS: u8 = $bits;
+
+            fn write_bits(&mut self, value: u8, mask: u8) -> Result<(), Self::Error> {
+                $(write_bit(&mut self.$i, $i, value, mask).map_err(Error::$s)?;)+
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_select_port!(1: S0 0; S0, S0, S0, S0);
+impl_select_port!(2: S0 0, S1 1; S0, S0, S0);
+impl_select_port!(3: S0 0, S1 1, S2 2; S0, S0);
+impl_select_port!(4: S0 0, S1 1, S2 2, S3 3; S0);
+impl_select_port!(5: S0 0, S1 1, S2 2, S3 3, S4 4;);
+
+/// Drives the select pins (`pins[n]` is `Sn`) whose bit is set in
+/// `mask` to the level of that bit in `value`
+fn write_pins<S: Pin>(pins: &mut [S], value: u8, mask: u8) -> Result<(), Error<S::Error>> {
+    for (bit, pin) in (0..).zip(pins.iter_mut()) {
+        write_bit(pin, bit, value, mask).map_err(|e| match bit {
+            0 => Error::S0(e),
+            1 => Error::S1(e),
+            2 => Error::S2(e),
+            3 => Error::S3(e),
+            _ => Error::S4(e),
+        })?;
+    }
+    Ok(())
+}
+
+/// An array of 1 to 5 select pins of the same type (e.g. type-erased
+/// HAL pins).  More than 5 fails to compile once it's used with a
+/// `Multiplexer`.
+impl<S: Pin, const N: usize> SelectPort for [S; N] {
+    type Error = Error<S::Error>;
+    const BITS: u8 = {
+        assert!(N >= 1 && N <= 5, "Only 1 to 5 select pins are supported");
+        N as u8
+    };
+
+    fn write_bits(&mut self, value: u8, mask: u8) -> Result<(), Self::Error> {
+        write_pins(self, value, mask)
+    }
+}
+
+/// 1 to 5 select pins of the same type whose number is only known at
+/// runtime, e.g. a slice of `&mut dyn OutputPin<Error = E>`s picked
+/// from the board revision:
+///
+/// ```ignore
+/// let select = SelectSlice::new(&mut pins[..num_select_pins]).unwrap();
+/// let mut multiplexer = Multiplexer::new(PortOutput::new(select, en)).unwrap();
+/// ```
+///
+/// Since the number of channels is only known at runtime
+/// (`multiplexer.num_channels`) it can't be used with anything that
+/// needs it at compile time (see `Output::NUM_CHANNELS`):
+///
+/// ```compile_fail
+/// # use analog_multiplexer::{Bank, Multiplexer, NoEnable, PortOutput, SelectSlice};
+/// let mut pins = [NoEnable, NoEnable];
+/// let select = PortOutput::without_enable(SelectSlice::new(&mut pins).unwrap());
+/// let bank = Bank::new(select, [NoEnable, NoEnable]); // Error
+/// let multiplexer = Multiplexer::new(bank);
+/// ```
+pub struct SelectSlice<'a, S> {
+    pins: &'a mut [S],
+}
+
+impl<'a, S: Pin> SelectSlice<'a, S> {
+    /// Returns `None` unless there are 1 to 5 select pins
+    pub fn new(pins: &'a mut [S]) -> Option<Self> {
+        if (1..=5).contains(&pins.len()) {
+            Some(Self { pins })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the select pins
+    pub fn release(self) -> &'a mut [S] {
+        self.pins
+    }
+}
+
+impl<S: Pin> SelectPort for SelectSlice<'_, S> {
+    type Error = Error<S::Error>;
+    const BITS: u8 = 0;
+
+    fn write_bits(&mut self, value: u8, mask: u8) -> Result<(), Self::Error> {
+        write_pins(self.pins, value, mask)
+    }
+
+    fn bits(&self) -> u8 {
+        self.pins.len() as u8
+    }
+}
+
+/// Errors that can occur while driving a `PortOutput`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortError<S, E> {
+    /// Writing the select lines failed
+    Select(S),
+    /// Setting the `EN` (aka "Inhibit") pin failed
+    EN(E),
+}
+
+/// A `SelectPort` plus an `EN` pin.  It implements `Output` so it can
+/// be handed to `Multiplexer::new()` in place of a pin tuple.
+pub struct PortOutput<P, EN = NoEnable> {
+    pub port: P,
+    pub en: EN,
+}
+
+impl<P: SelectPort, EN: Pin> PortOutput<P, EN> {
+    pub fn new(port: P, en: EN) -> Self {
+        Self { port, en }
+    }
+
+    /// Returns the port and the `EN` pin
+    pub fn release(self) -> (P, EN) {
+        (self.port, self.en)
+    }
+}
+
+impl<P: SelectPort> PortOutput<P, NoEnable> {
+    /// Returns a `PortOutput` for a multiplexer whose `EN` pin is run
+    /// to GND (always enabled)
+    pub fn without_enable(port: P) -> Self {
+        Self::new(port, NoEnable)
+    }
+}
+
+impl<P: SelectPort, EN: Pin> Output for PortOutput<P, EN> {
+    type Error = PortError<P::Error, EN::Error>;
+    const NUM_CHANNELS: u8 = if P::BITS == 0 { 0 } else { 1 << P::BITS };
+    const HAS_ENABLE: bool = EN::CONNECTED;
+
+    /// Writes every select line at once
+    fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error> {
+        let mask = self.num_channels() - 1;
+        self.port
+            .write_bits(channel, mask)
+            .map_err(PortError::Select)
+    }
+
+    /// Brings the `EN` pin low to enable the multiplexer
+    fn enable(&mut self) -> Result<(), Self::Error> {
+        self.en.set_low().map_err(PortError::EN)
+    }
+
+    /// Brings the `EN` pin high to disable the multiplexer
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        self.en.set_high().map_err(PortError::EN)
+    }
+
+    /// Writes the select lines that change at once (or nothing if
+    /// none of them do)
+    fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+        let changed = (from ^ to) & (self.num_channels() - 1);
+        if changed == 0 {
+            return Ok(());
+        }
+        self.port.write_bits(to, changed).map_err(PortError::Select)
+    }
+
+    /// Returns `1 << bits` for a port with `bits` select lines
+    fn num_channels(&self) -> u8 {
+        1 << self.port.bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Line, MockMux};
+    use crate::Multiplexer;
+    use core::convert::Infallible;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    /// Records every `(value, mask)` write
+    struct RecordingPort(Rc<RefCell<Vec<(u8, u8)>>>);
+
+    impl SelectPort for RecordingPort {
+        type Error = Infallible;
+        const BITS: u8 = 4;
+
+        fn write_bits(&mut self, value: u8, mask: u8) -> Result<(), Infallible> {
+            self.0.borrow_mut().push((value, mask));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn switches_every_select_line_in_one_write() {
+        let writes = Rc::new(RefCell::new(Vec::new()));
+        let port = PortOutput::without_enable(RecordingPort(writes.clone()));
+        let mut multiplexer = Multiplexer::new(port).unwrap();
+        assert_eq!(multiplexer.num_channels, 16);
+        multiplexer.set_channel(7).unwrap();
+        multiplexer.set_channel(8).unwrap();
+        multiplexer.set_channel(8).unwrap();
+        multiplexer.set_channel(9).unwrap();
+        assert_eq!(*writes.borrow(), [(0, 0xF), (7, 0x7), (8, 0xF), (9, 0x1)]);
+    }
+
+    #[test]
+    fn pin_tuples_keep_working_as_ports() {
+        let mock = MockMux::new();
+        let (s0, s1, s2, en) = mock.pins8();
+        let mut multiplexer = Multiplexer::new(PortOutput::new((s0, s1, s2), en)).unwrap();
+        assert_eq!(multiplexer.num_channels, 8);
+        multiplexer.set_channel(5).unwrap();
+        assert_eq!(mock.selected(), Some(5));
+        mock.set_failing(Line::Select(1), true);
+        assert_eq!(
+            multiplexer.set_channel(6),
+            Err(PortError::Select(Error::S1(crate::mock::MockError(
+                Line::Select(1)
+            ))))
+        );
+        multiplexer.disable().unwrap();
+        assert_eq!(mock.selected(), None);
+    }
+}
@@ -0,0 +1,185 @@
+//! Remapping select lines and channels to match how the PCB is wired.
+//!
+//! `Rewired` fixes select lines that were connected out of order (e.g.
+//! to make routing easier) and `ChannelMap` maps channel numbers to the
+//! multiplexer inputs they should reach (e.g. to match the silkscreen).
+//! Both implement `Output` so they wrap a pin tuple (or each other):
+//!
+//! ```ignore
+//! // The 1st pin in the tuple goes to S1 and the 2nd goes to S0:
+//! let pins = Rewired::new((pb3, pb4, pb5, pb6, en), [1, 0, 2, 3]).unwrap();
+//! // The input labelled "0" on the silkscreen is the multiplexer's input 15, etc:
+//! let pins = ChannelMap::new(pins, [15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]).unwrap();
+//! let mut multiplexer = Multiplexer::new(pins).unwrap();
+//! multiplexer.set_channel(3).unwrap(); // Reaches the input labelled "3"
+//! ```
+
+use crate::Output;
+
+/// Wraps an `Output` whose select pins are wired to the multiplexer's
+/// select lines in a different order.  `lines[n]` is the select line
+/// (0 for `S0`, etc) that the `Output`'s `n`th select pin is connected
+/// to.
+pub struct Rewired<Pins, const B: usize> {
+    pub pins: Pins,
+    lines: [u8; B],
+}
+
+impl<Pins: Output, const B: usize> Rewired<Pins, B> {
+    /// Returns `None` if `lines` isn't a permutation of `0..B`.  `B`
+    /// must match the number of select pins; a mismatch fails to
+    /// compile once it's used with a `Multiplexer`.
+    pub fn new(pins: Pins, lines: [u8; B]) -> Option<Self> {
+        for line in 0..B as u8 {
+            if !lines.contains(&line) {
+                return None;
+            }
+        }
+        Some(Self { pins, lines })
+    }
+
+    /// Returns the select pin levels that select the given channel
+    fn code(&self, channel: u8) -> u8 {
+        let mut code = 0;
+        for (bit, line) in self.lines.iter().enumerate() {
+            if channel & (1 << line) != 0 {
+                code |= 1 << bit;
+            }
+        }
+        code
+    }
+
+    /// Returns the wrapped `Output`
+    pub fn release(self) -> Pins {
+        self.pins
+    }
+}
+
+impl<Pins: Output, const B: usize> Output for Rewired<Pins, B> {
+    type Error = Pins::Error;
+    const NUM_CHANNELS: u8 = {
+        assert!(
+            B < 8 && Pins::NUM_CHANNELS as usize == 1 << B,
+            "Rewired<Pins, B> doesn't match the number of select pins"
+        );
+        Pins::NUM_CHANNELS
+    };
+    const HAS_ENABLE: bool = Pins::HAS_ENABLE;
+
+    fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error> {
+        let code = self.code(channel);
+        self.pins.set_channel(code)
+    }
+
+    fn enable(&mut self) -> Result<(), Self::Error> {
+        self.pins.enable()
+    }
+
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        self.pins.disable()
+    }
+
+    fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+        let (from, to) = (self.code(from), self.code(to));
+        self.pins.switch_channel(from, to)
+    }
+}
+
+/// Wraps an `Output` so channel `n` selects the multiplexer's input
+/// `channels[n]` (e.g. to match the labels on the silkscreen).
+pub struct ChannelMap<Pins, const N: usize> {
+    pub pins: Pins,
+    channels: [u8; N],
+}
+
+impl<Pins: Output, const N: usize> ChannelMap<Pins, N> {
+    /// Returns `None` if any of the `channels` are out of range.  `N`
+    /// must match the number of channels; a mismatch fails to compile
+    /// once it's used with a `Multiplexer`.
+    pub fn new(pins: Pins, channels: [u8; N]) -> Option<Self> {
+        if channels.iter().any(|input| *input >= Pins::NUM_CHANNELS) {
+            return None;
+        }
+        Some(Self { pins, channels })
+    }
+
+    /// Returns the multiplexer input the given channel selects
+    pub fn input(&self, channel: u8) -> u8 {
+        self.channels[channel as usize % N]
+    }
+
+    /// Returns the wrapped `Output`
+    pub fn release(self) -> Pins {
+        self.pins
+    }
+}
+
+impl<Pins: Output, const N: usize> Output for ChannelMap<Pins, N> {
+    type Error = Pins::Error;
+    const NUM_CHANNELS: u8 = {
+        assert!(
+            N == Pins::NUM_CHANNELS as usize && N != 0,
+            "ChannelMap<Pins, N> doesn't match the number of channels"
+        );
+        Pins::NUM_CHANNELS
+    };
+    const HAS_ENABLE: bool = Pins::HAS_ENABLE;
+
+    fn set_channel(&mut self, channel: u8) -> Result<(), Self::Error> {
+        let input = self.input(channel);
+        self.pins.set_channel(input)
+    }
+
+    fn enable(&mut self) -> Result<(), Self::Error> {
+        self.pins.enable()
+    }
+
+    fn disable(&mut self) -> Result<(), Self::Error> {
+        self.pins.disable()
+    }
+
+    fn switch_channel(&mut self, from: u8, to: u8) -> Result<(), Self::Error> {
+        let (from, to) = (self.input(from), self.input(to));
+        self.pins.switch_channel(from, to)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Line, MockMux};
+    use crate::Multiplexer;
+
+    #[test]
+    fn rewired_select_lines() {
+        let mock = MockMux::new();
+        // The tuple's pins are really connected to S2, S0, and S1
+        let (a, b, c, en) = mock.pins8();
+        let pins = Rewired::new((c, a, b, en), [2, 0, 1]).unwrap();
+        let mut multiplexer = Multiplexer::new(pins).unwrap();
+        for chan in 0..8 {
+            multiplexer.set_channel(chan).unwrap();
+            assert_eq!(mock.selected(), Some(chan));
+        }
+        assert!(Rewired::new(mock.pins8(), [0, 0, 1]).is_none());
+    }
+
+    #[test]
+    fn channel_map_reaches_the_labelled_input() {
+        let mock = MockMux::new();
+        let map = [7, 6, 5, 4, 0, 1, 2, 3];
+        let pins = ChannelMap::new(mock.pins8(), map).unwrap();
+        let mut multiplexer = Multiplexer::new(pins).unwrap();
+        assert_eq!(mock.selected(), Some(7)); // Initialized to channel 0
+        for chan in 0..8 {
+            multiplexer.set_channel(chan).unwrap();
+            assert_eq!(mock.selected(), Some(map[chan as usize]));
+        }
+        // Only the select lines that change between inputs get written
+        let seq = mock.seq();
+        multiplexer.set_channel(6).unwrap(); // Input 3 -> 2
+        assert_eq!(mock.seq() - seq, 1);
+        assert_eq!(mock.level(Line::Select(0)), Some(false));
+        assert!(ChannelMap::new(mock.pins8(), [8; 8]).is_none());
+    }
+}
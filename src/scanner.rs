@@ -0,0 +1,321 @@
+//! A non-blocking (`nb`) scan state machine for interrupt-driven or
+//! bare-metal loops without an async runtime.
+//!
+//! Every call to `Scanner::poll()` advances the scan by (at most) one
+//! step: switch to the next channel, wait for its settle deadline (using
+//! a user-supplied tick source), start the conversion, then collect the
+//! result.  It returns `nb::Error::WouldBlock` until a complete
+//! `Snapshot` is available, so it never busy-waits:
+//!
+//! ```ignore
+//! let mut scanner: Scanner<_, _, _, _, 16> =
+//!     Scanner::new(multiplexer.with_adc(adc1, analog_pin), || timer.now_us());
+//! loop {
+//!     usb_dev.poll(&mut [&mut serial]); // Interleaved with other work
+//!     if let Ok(snapshot) = scanner.poll() {
+//!         // Do something with the snapshot
+//!     }
+//! }
+//! ```
+
+use core::convert::Infallible;
+
+use eh0::adc::{Channel, OneShot};
+
+use crate::snapshot::SnapshotSize;
+use crate::{
+    AnalogMultiplexer, ChannelError, Channels, NoDelay, Output, ReadError, ScanOrder, Snapshot,
+};
+
+/// A source of microsecond ticks for timing settle deadlines.  The
+/// count is expected to wrap around.  It's implemented for any
+/// `FnMut() -> u32` closure.
+pub trait Clock {
+    fn now_us(&mut self) -> u32;
+}
+
+impl<F: FnMut() -> u32> Clock for F {
+    fn now_us(&mut self) -> u32 {
+        self()
+    }
+}
+
+/// Where the `Scanner` is at in the scan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanState {
+    /// The next step switches to this channel
+    Select(u8),
+    /// Waiting for this channel to settle
+    Settling {
+        channel: u8,
+        since: u32,
+        settle_us: u32,
+    },
+    /// Converting this channel
+    Converting(u8),
+    /// There's nothing to scan (the scan order is empty) so every
+    /// `poll()` returns the `Snapshot` as it is
+    Idle,
+}
+
+/// A non-blocking scanner around an `AnalogMultiplexer` (see the
+/// module documentation).  The `Multiplexer`'s `settle_us` (and
+/// per-channel overrides) are honoured using the `Clock` instead of
+/// a blocking delay.
+pub struct Scanner<Pins, Adc, Z, C, const N: usize, const T: usize = 0> {
+    pub multiplexer: AnalogMultiplexer<Pins, Adc, Z, NoDelay, T>,
+    pub clock: C,
+    order: ScanOrder<'static>,
+    /// The channels left to visit in this scan
+    channels: Channels<'static>,
+    state: ScanState,
+    snapshot: Snapshot<N>,
+}
+
+impl<Pins: Output, Adc, Z, C: Clock, const N: usize, const T: usize>
+    Scanner<Pins, Adc, Z, C, N, T>
+{
+    /// Returns a new `Scanner` that will scan the channels in ascending
+    /// order.  `N` must match the multiplexer's number of channels (e.g.
+    /// `Scanner<_, _, _, _, 16>` for a 74HC4067); a mismatch fails to
+    /// compile.
+    pub fn new(multiplexer: AnalogMultiplexer<Pins, Adc, Z, NoDelay, T>, clock: C) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = SnapshotSize::<Pins, N>::OK;
+        let mut scanner = Self {
+            multiplexer,
+            clock,
+            order: ScanOrder::Ascending,
+            channels: ScanOrder::Ascending.channels(0),
+            state: ScanState::Select(0),
+            snapshot: Snapshot::new(),
+        };
+        scanner.restart();
+        scanner
+    }
+
+    /// Scans the channels in the given order from now on (starting
+    /// over).  Channels that aren't part of the order keep whatever
+    /// value they had in the `Snapshot`.
+    ///
+    /// Returns `ChannelError::InvalidChannel` (and keeps scanning in
+    /// the old order) if a `ScanOrder::Custom` channel is out of range
+    /// for the multiplexer.
+    pub fn set_order(&mut self, order: ScanOrder<'static>) -> Result<(), ChannelError<Infallible>> {
+        let num_channels = self.multiplexer.multiplexer.num_channels;
+        if let Some(channel) = order.channels(num_channels).find(|&c| c >= num_channels) {
+            return Err(ChannelError::InvalidChannel(channel));
+        }
+        self.order = order;
+        self.restart();
+        Ok(())
+    }
+
+    /// Returns where the scanner is at in the scan
+    pub fn state(&self) -> ScanState {
+        self.state
+    }
+
+    /// Abandons the scan in progress and starts over at the first
+    /// channel in the scan order
+    pub fn restart(&mut self) {
+        self.channels = self
+            .order
+            .channels(self.multiplexer.multiplexer.num_channels);
+        self.state = match self.channels.next() {
+            Some(channel) => ScanState::Select(channel),
+            None => ScanState::Idle,
+        };
+    }
+
+    /// Advances the scan by one step.  Returns the `Snapshot` once
+    /// every channel has been read (the next call starts a new scan)
+    /// and `nb::Error::WouldBlock` until then.  If an error occurs the
+    /// step that failed will be retried on the next call.
+    pub fn poll<ADC>(&mut self) -> nb::Result<Snapshot<N>, ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        match self.state {
+            ScanState::Select(channel) => {
+                let multiplexer = &mut self.multiplexer.multiplexer;
+                let switched = multiplexer.switching(channel);
+                multiplexer
+                    .try_set_channel(channel)
+                    .map_err(|e| nb::Error::Other(e.into()))?;
+                let settle_us = if switched {
+                    multiplexer.settle_time(channel)
+                } else {
+                    0
+                };
+                self.state = ScanState::Settling {
+                    channel,
+                    since: self.clock.now_us(),
+                    settle_us,
+                };
+                Err(nb::Error::WouldBlock)
+            }
+            ScanState::Settling {
+                channel,
+                since,
+                settle_us,
+            } => {
+                if self.clock.now_us().wrapping_sub(since) < settle_us {
+                    return Err(nb::Error::WouldBlock);
+                }
+                self.state = ScanState::Converting(channel);
+                self.convert(channel)
+            }
+            ScanState::Converting(channel) => self.convert(channel),
+            ScanState::Idle => Ok(self.snapshot),
+        }
+    }
+
+    /// Starts (or checks on) the conversion of the given channel
+    fn convert<ADC>(
+        &mut self,
+        channel: u8,
+    ) -> nb::Result<Snapshot<N>, ReadError<Pins::Error, Adc::Error>>
+    where
+        Adc: OneShot<ADC, u16, Z>,
+        Z: Channel<ADC>,
+    {
+        let multiplexer = &mut self.multiplexer;
+        let value = match multiplexer.adc.read(&mut multiplexer.pin) {
+            Ok(value) => value,
+            Err(nb::Error::WouldBlock) => return Err(nb::Error::WouldBlock),
+            Err(nb::Error::Other(e)) => {
+                self.state = ScanState::Select(channel);
+                return Err(nb::Error::Other(ReadError::Adc(e)));
+            }
+        };
+        if let Some(slot) = self.snapshot.values.get_mut(channel as usize) {
+            *slot = value;
+        }
+        match self.channels.next() {
+            Some(next) => {
+                self.state = ScanState::Select(next);
+                Err(nb::Error::WouldBlock)
+            }
+            None => {
+                self.restart();
+                Ok(self.snapshot)
+            }
+        }
+    }
+
+    /// Returns the `AnalogMultiplexer` and the `Clock`
+    pub fn release(self) -> (AnalogMultiplexer<Pins, Adc, Z, NoDelay, T>, C) {
+        (self.multiplexer, self.clock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockMux, MockZ};
+    use crate::Multiplexer;
+    use core::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn poll_steps_through_a_full_scan() {
+        let mock = MockMux::new();
+        let now = Rc::new(Cell::new(0u32));
+        let mut multiplexer = Multiplexer::new(mock.pins8()).unwrap();
+        multiplexer.settle_us = 10;
+        // Conversions take two reads
+        let adc = mock.adc(100).two_step();
+        let clock = {
+            let now = now.clone();
+            move || now.get()
+        };
+        let mut scanner: Scanner<_, _, _, _, 8> =
+            Scanner::new(multiplexer.with_adc(adc, MockZ::<0>), clock);
+        // Channel 0 is already selected so there's nothing to wait for
+        assert_eq!(scanner.poll(), Err(nb::Error::WouldBlock)); // Select
+        assert_eq!(scanner.poll(), Err(nb::Error::WouldBlock)); // Start converting
+        assert_eq!(scanner.poll(), Err(nb::Error::WouldBlock)); // Collect
+        assert_eq!(scanner.state(), ScanState::Select(1));
+        assert_eq!(scanner.poll(), Err(nb::Error::WouldBlock)); // Select
+        assert_eq!(scanner.poll(), Err(nb::Error::WouldBlock)); // Still settling
+        assert_eq!(
+            scanner.state(),
+            ScanState::Settling {
+                channel: 1,
+                since: 0,
+                settle_us: 10
+            }
+        );
+        now.set(10);
+        let mut polls = 0;
+        let snapshot = loop {
+            polls += 1;
+            match scanner.poll() {
+                Ok(snapshot) => break snapshot,
+                Err(nb::Error::WouldBlock) => now.set(now.get() + 5),
+                Err(nb::Error::Other(e)) => panic!("{:?}", e),
+            }
+        };
+        for (chan, value) in snapshot.iter() {
+            assert_eq!(value, chan as u16 * 100);
+        }
+        // 6 remaining channels take 4 polls each (select, 2x settle, convert)
+        assert_eq!(polls, 2 + 6 * 4);
+        assert_eq!(scanner.state(), ScanState::Select(0));
+    }
+
+    #[test]
+    fn custom_order_only_reads_those_channels() {
+        let mock = MockMux::new();
+        let adc = mock.adc(100).two_step();
+        let multiplexer = Multiplexer::new(mock.pins8()).unwrap();
+        let mut scanner: Scanner<_, _, _, _, 8> =
+            Scanner::new(multiplexer.with_adc(adc, MockZ::<0>), || 0);
+        scanner.set_order(ScanOrder::Custom(&[6, 2])).unwrap();
+        assert_eq!(scanner.state(), ScanState::Select(6));
+        let snapshot = loop {
+            match scanner.poll() {
+                Ok(snapshot) => break snapshot,
+                Err(nb::Error::WouldBlock) => {}
+                Err(nb::Error::Other(e)) => panic!("{:?}", e),
+            }
+        };
+        assert_eq!(snapshot.values, [0, 0, 200, 0, 0, 0, 600, 0]);
+        assert_eq!(scanner.state(), ScanState::Select(6));
+    }
+
+    #[test]
+    fn out_of_range_custom_order_is_rejected() {
+        let mock = MockMux::new();
+        let adc = mock.adc(100).two_step();
+        let multiplexer = Multiplexer::new(mock.pins8()).unwrap();
+        let mut scanner: Scanner<_, _, _, _, 8> =
+            Scanner::new(multiplexer.with_adc(adc, MockZ::<0>), || 0);
+        assert_eq!(
+            scanner.set_order(ScanOrder::Custom(&[3, 9, 1])),
+            Err(ChannelError::InvalidChannel(9))
+        );
+        // Still scanning in the old order
+        assert_eq!(scanner.state(), ScanState::Select(0));
+    }
+
+    #[test]
+    fn empty_custom_order_idles() {
+        let mock = MockMux::new();
+        let adc = mock.adc(100).two_step();
+        let multiplexer = Multiplexer::new(mock.pins8()).unwrap();
+        let mut scanner: Scanner<_, _, _, _, 8> =
+            Scanner::new(multiplexer.with_adc(adc, MockZ::<0>), || 0);
+        scanner.set_order(ScanOrder::Custom(&[])).unwrap();
+        assert_eq!(scanner.state(), ScanState::Idle);
+        let seq = mock.seq();
+        assert_eq!(scanner.poll().map(|s| s.values), Ok([0; 8]));
+        assert_eq!(scanner.poll().map(|s| s.values), Ok([0; 8]));
+        assert_eq!(scanner.state(), ScanState::Idle);
+        assert_eq!(mock.seq(), seq); // Channel 0 wasn't selected (or read)
+        scanner.set_order(ScanOrder::Custom(&[5])).unwrap();
+        assert_eq!(scanner.state(), ScanState::Select(5));
+    }
+}
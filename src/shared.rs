@@ -0,0 +1,257 @@
+//! Sharing one `AnalogMultiplexer` between several drivers.
+//!
+//! `SharedMultiplexer` splits a multiplexer into per-channel "ADC pins"
+//! (`MuxPin<CH>`, which implement `adc::Channel`) and a cheap, `Copy`able
+//! ADC handle (`MuxAdc`, which implements `adc::OneShot`).  Reading a
+//! `MuxPin` through a `MuxAdc` selects that pin's channel on the
+//! multiplexer and then performs the conversion, so driver crates that
+//! expect an ADC + pin pair (thermistors, joysticks, battery monitors,
+//! etc) can use multiplexer channels unchanged:
+//!
+//! ```ignore
+//! let shared = SharedMultiplexer::new(multiplexer.with_adc(adc1, analog_pin));
+//! let (adc, pins) = shared.split();
+//! let mut thermistor = Thermistor::new(adc, pins.ch3);
+//! let mut joystick_x = Joystick::new(adc, pins.ch7);
+//! ```
+
+use core::cell::{Cell, RefCell};
+use core::marker::PhantomData;
+
+use eh0::adc::{Channel, OneShot};
+
+use crate::{AnalogMultiplexer, Delay, NoDelay, Output, ReadError};
+
+/// The "ADC" that `MuxPin`s are channels of (as far as embedded-hal's
+/// `adc::Channel` trait is concerned)
+pub struct Mux;
+
+/// A single multiplexer channel that can be read through a `MuxAdc`
+/// like any other analog pin
+pub struct MuxPin<const CH: u8>;
+
+impl<const CH: u8> MuxPin<CH> {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<const CH: u8> Default for MuxPin<CH> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const CH: u8> Channel<Mux> for MuxPin<CH> {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        CH
+    }
+}
+
+/// Every channel of a 16-channel multiplexer as a `MuxPin` (as
+/// returned by `SharedMultiplexer::split()`).
+///
+/// **NOTE:** `ch8` through `ch15` are only valid when using a
+/// 16-channel multiplexer (e.g. 74HC4067).
+pub struct MuxPins {
+    pub ch0: MuxPin<0>,
+    pub ch1: MuxPin<1>,
+    pub ch2: MuxPin<2>,
+    pub ch3: MuxPin<3>,
+    pub ch4: MuxPin<4>,
+    pub ch5: MuxPin<5>,
+    pub ch6: MuxPin<6>,
+    pub ch7: MuxPin<7>,
+    pub ch8: MuxPin<8>,
+    pub ch9: MuxPin<9>,
+    pub ch10: MuxPin<10>,
+    pub ch11: MuxPin<11>,
+    pub ch12: MuxPin<12>,
+    pub ch13: MuxPin<13>,
+    pub ch14: MuxPin<14>,
+    pub ch15: MuxPin<15>,
+}
+
+/// An `AnalogMultiplexer` that can be read from several places at
+/// once via `MuxAdc` handles.  Access is arbitrated by a `RefCell`
+/// and the channel whose conversion is in progress (if any) owns the
+/// multiplexer until that conversion completes.
+pub struct SharedMultiplexer<Pins, Adc, Z, D = NoDelay, const T: usize = 0> {
+    inner: RefCell<AnalogMultiplexer<Pins, Adc, Z, D, T>>,
+    /// The channel that started the conversion that's in progress
+    converting: Cell<Option<u8>>,
+}
+
+impl<Pins: Output, Adc, Z, D: Delay, const T: usize> SharedMultiplexer<Pins, Adc, Z, D, T> {
+    pub fn new(multiplexer: AnalogMultiplexer<Pins, Adc, Z, D, T>) -> Self {
+        Self {
+            inner: RefCell::new(multiplexer),
+            converting: Cell::new(None),
+        }
+    }
+
+    /// Returns the channel whose conversion is in progress (if any)
+    pub fn converting(&self) -> Option<u8> {
+        self.converting.get()
+    }
+
+    /// Returns a `MuxAdc` handle that can read any `MuxPin`
+    pub fn adc<ADC>(&self) -> MuxAdc<'_, Pins, Adc, Z, ADC, D, T> {
+        MuxAdc {
+            shared: self,
+            _adc: PhantomData,
+        }
+    }
+
+    /// Splits the multiplexer into a `MuxAdc` handle and a `MuxPin`
+    /// for every channel
+    pub fn split<ADC>(&self) -> (MuxAdc<'_, Pins, Adc, Z, ADC, D, T>, MuxPins) {
+        let pins = MuxPins {
+            ch0: MuxPin,
+            ch1: MuxPin,
+            ch2: MuxPin,
+            ch3: MuxPin,
+            ch4: MuxPin,
+            ch5: MuxPin,
+            ch6: MuxPin,
+            ch7: MuxPin,
+            ch8: MuxPin,
+            ch9: MuxPin,
+            ch10: MuxPin,
+            ch11: MuxPin,
+            ch12: MuxPin,
+            ch13: MuxPin,
+            ch14: MuxPin,
+            ch15: MuxPin,
+        };
+        (self.adc(), pins)
+    }
+
+    /// Returns the wrapped `AnalogMultiplexer`
+    pub fn into_inner(self) -> AnalogMultiplexer<Pins, Adc, Z, D, T> {
+        self.inner.into_inner()
+    }
+}
+
+/// A handle to a `SharedMultiplexer` that implements `adc::OneShot`
+/// for every `MuxPin`.  Reading a `MuxPin` selects its channel on the
+/// multiplexer (waiting for it to settle if a delay was configured)
+/// then starts (or continues) the conversion.  If the
+/// multiplexer is already in use (including while another channel's
+/// conversion is still in progress) `nb::Error::WouldBlock` is
+/// returned.  Reading a `MuxPin` that's out of range for the
+/// multiplexer returns `ReadError::InvalidChannel`.
+pub struct MuxAdc<'a, Pins, Adc, Z, ADC, D = NoDelay, const T: usize = 0> {
+    shared: &'a SharedMultiplexer<Pins, Adc, Z, D, T>,
+    _adc: PhantomData<ADC>,
+}
+
+impl<'a, Pins, Adc, Z, ADC, D, const T: usize> Clone for MuxAdc<'a, Pins, Adc, Z, ADC, D, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, Pins, Adc, Z, ADC, D, const T: usize> Copy for MuxAdc<'a, Pins, Adc, Z, ADC, D, T> {}
+
+impl<'a, Pins, Adc, Z, ADC, D, const T: usize, const CH: u8> OneShot<Mux, u16, MuxPin<CH>>
+    for MuxAdc<'a, Pins, Adc, Z, ADC, D, T>
+where
+    Pins: Output,
+    D: Delay,
+    Adc: OneShot<ADC, u16, Z>,
+    Z: Channel<ADC>,
+{
+    type Error = ReadError<Pins::Error, Adc::Error>;
+
+    fn read(&mut self, _pin: &mut MuxPin<CH>) -> nb::Result<u16, Self::Error> {
+        let shared = self.shared;
+        let mut inner = shared
+            .inner
+            .try_borrow_mut()
+            .map_err(|_| nb::Error::WouldBlock)?;
+        let inner = &mut *inner;
+        match shared.converting.get() {
+            // Switching channels now would corrupt the other conversion
+            Some(channel) if channel != CH => return Err(nb::Error::WouldBlock),
+            Some(_) => {}
+            None => inner
+                .multiplexer
+                .try_set_channel(CH)
+                .map_err(|e| nb::Error::Other(e.into()))?,
+        }
+        let result = inner.adc.read(&mut inner.pin);
+        let pending = matches!(result, Err(nb::Error::WouldBlock));
+        shared.converting.set(if pending { Some(CH) } else { None });
+        result.map_err(|e| e.map(ReadError::Adc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockMux, MockZ};
+    use crate::sim::{SimPin, Simulator, Source};
+    use crate::Multiplexer;
+
+    #[test]
+    fn split_pins_read_their_channels() {
+        let sim = Simulator::new(16);
+        sim.set_source(3, Source::Constant(300));
+        sim.set_source(12, Source::Constant(1200));
+        let multiplexer = Multiplexer::new(sim.pins16())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        let shared = SharedMultiplexer::new(multiplexer);
+        let (mut adc, mut pins) = shared.split();
+        assert_eq!(adc.read(&mut pins.ch3), Ok(300));
+        assert_eq!(adc.read(&mut pins.ch12), Ok(1200));
+        assert_eq!(shared.into_inner().multiplexer.active_channel, 12);
+    }
+
+    #[test]
+    fn out_of_range_pins_are_invalid() {
+        let sim = Simulator::new(8);
+        sim.set_source(2, Source::Constant(200));
+        let multiplexer = Multiplexer::new(sim.pins8())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        let shared = SharedMultiplexer::new(multiplexer);
+        let (mut adc, mut pins) = shared.split();
+        assert_eq!(
+            adc.read(&mut pins.ch8),
+            Err(nb::Error::Other(ReadError::InvalidChannel(8)))
+        );
+        assert_eq!(
+            adc.read(&mut pins.ch15),
+            Err(nb::Error::Other(ReadError::InvalidChannel(15)))
+        );
+        assert_eq!(adc.read(&mut pins.ch2), Ok(200));
+    }
+
+    #[test]
+    fn interleaved_readers_wait_for_the_conversion_in_progress() {
+        let mock = MockMux::new();
+        // Conversions take two reads (and come back `MockAdc::MIXED` if the
+        // channel changed in the meantime)
+        let adc = mock.adc(10).two_step();
+        let multiplexer = Multiplexer::new(mock.pins16())
+            .unwrap()
+            .with_adc(adc, MockZ::<0>);
+        let shared = SharedMultiplexer::new(multiplexer);
+        let (mut thermistor, pins) = shared.split();
+        let mut joystick = thermistor;
+        let (mut ch3, mut ch7) = (pins.ch3, pins.ch7);
+        assert_eq!(thermistor.read(&mut ch3), Err(nb::Error::WouldBlock));
+        assert_eq!(shared.converting(), Some(3));
+        // ch3's conversion owns the multiplexer until it completes
+        assert_eq!(joystick.read(&mut ch7), Err(nb::Error::WouldBlock));
+        assert_eq!(mock.selected(), Some(3));
+        assert_eq!(thermistor.read(&mut ch3), Ok(30));
+        assert_eq!(shared.converting(), None);
+        assert_eq!(nb::block!(joystick.read(&mut ch7)), Ok(70));
+        assert_eq!(nb::block!(thermistor.read(&mut ch3)), Ok(30));
+    }
+}
@@ -0,0 +1,432 @@
+//! A software-simulated 74HC4051/74HC4067 with virtual analog sources
+//! (enable the `mock` and `eh0` features).
+//!
+//! The `Simulator` hands out `MockPin`s for the `Multiplexer` and decodes
+//! the levels they're actually driven to (honouring `EN`) to decide
+//! which channel's `Source` is routed to its ADC (`SimAdc`, which
+//! implements `adc::OneShot`).  That way an entire scan loop can run in
+//! `cargo test`:
+//!
+//! ```
+//! use analog_multiplexer::sim::{SimPin, Simulator, Source};
+//! use analog_multiplexer::Multiplexer;
+//!
+//! let sim = Simulator::new(16);
+//! sim.set_source(3, Source::Constant(1234));
+//! sim.set_source(7, Source::Ramp { from: 0, to: 4095, period_us: 1000 });
+//! let mut multiplexer = Multiplexer::new(sim.pins16())
+//!     .unwrap()
+//!     .with_adc(sim.adc(), SimPin);
+//! let mut values = [0u16; 16];
+//! multiplexer.read_all(&mut values).unwrap();
+//! assert_eq!(values[3], 1234);
+//! ```
+//!
+//! Time only moves forward when the ADC converts (`conversion_us`) or
+//! when something waits using the simulator's `SimDelay` (e.g. as the
+//! `Multiplexer`'s settling delay).  That's the clock `Source`s and the
+//! optional settling model are driven by.
+
+use std::boxed::Box;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use core::convert::Infallible;
+
+use eh0::adc::{Channel, OneShot};
+
+use crate::mock::{MockMux, MockPin};
+use crate::Delay;
+
+/// A virtual analog signal connected to one of the simulated channels.
+/// Every variant is a function of the simulator's clock (in µs).
+pub enum Source {
+    /// Always the same value
+    Constant(u16),
+    /// A sawtooth going from `from` to `to` every `period_us`
+    Ramp { from: u16, to: u16, period_us: u32 },
+    /// A sine wave centered on `offset`
+    Sine {
+        offset: u16,
+        amplitude: u16,
+        period_us: u32,
+    },
+    /// Recorded samples (one every `interval_us`), looped forever
+    Trace { samples: Vec<u16>, interval_us: u32 },
+    /// Anything else: called with the simulator's clock (in µs)
+    Func(Box<dyn FnMut(u64) -> u16>),
+}
+
+impl Source {
+    fn value(&mut self, now_us: u64) -> u16 {
+        match self {
+            Source::Constant(value) => *value,
+            Source::Ramp {
+                from,
+                to,
+                period_us,
+            } => {
+                let period = (*period_us).max(1) as u64;
+                let phase = (now_us % period) as f32 / period as f32;
+                (*from as f32 + (*to as f32 - *from as f32) * phase) as u16
+            }
+            Source::Sine {
+                offset,
+                amplitude,
+                period_us,
+            } => {
+                let period = (*period_us).max(1) as u64;
+                let phase = (now_us % period) as f32 / period as f32;
+                let value = *offset as f32
+                    + *amplitude as f32 * (2.0 * core::f32::consts::PI * phase).sin();
+                value.max(0.0).min(u16::MAX as f32) as u16
+            }
+            Source::Trace {
+                samples,
+                interval_us,
+            } => {
+                if samples.is_empty() {
+                    return 0;
+                }
+                let index = now_us / (*interval_us).max(1) as u64;
+                samples[(index % samples.len() as u64) as usize]
+            }
+            Source::Func(f) => f(now_us),
+        }
+    }
+}
+
+/// A single simulated ADC conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Read {
+    /// The `MockMux` sequence number when the conversion happened
+    pub seq: u32,
+    /// The simulator's clock when the conversion happened
+    pub time_us: u64,
+    /// The channel that was electrically selected (`None` if disabled)
+    pub channel: Option<u8>,
+    pub value: u16,
+}
+
+struct State {
+    sources: Vec<Source>,
+    now_us: u64,
+    conversion_us: u32,
+    settling_tau_us: Option<f32>,
+    crosstalk: f32,
+    floating: u16,
+    /// The voltage on the common pin (as of `node_us`)
+    node: f32,
+    node_us: u64,
+    last_channel: Option<u8>,
+    last_value: f32,
+    reads: Vec<Read>,
+}
+
+/// A simulated analog multiplexer (see the module documentation)
+#[derive(Clone)]
+pub struct Simulator {
+    mock: MockMux,
+    state: Rc<RefCell<State>>,
+}
+
+impl Simulator {
+    /// Returns a simulator with `num_channels` channels, all connected
+    /// to `Source::Constant(0)`
+    pub fn new(num_channels: u8) -> Self {
+        let sources = (0..num_channels).map(|_| Source::Constant(0)).collect();
+        Self {
+            mock: MockMux::new(),
+            state: Rc::new(RefCell::new(State {
+                sources,
+                now_us: 0,
+                conversion_us: 10,
+                settling_tau_us: None,
+                crosstalk: 0.0,
+                floating: 0,
+                node: 0.0,
+                node_us: 0,
+                last_channel: None,
+                last_value: 0.0,
+                reads: Vec::new(),
+            })),
+        }
+    }
+
+    /// Returns the `MockMux` the simulator's pins come from
+    pub fn mock(&self) -> &MockMux {
+        &self.mock
+    }
+
+    /// Returns pins for a 16-channel multiplexer: `(s0, s1, s2, s3, en)`
+    pub fn pins16(&self) -> (MockPin, MockPin, MockPin, MockPin, MockPin) {
+        self.mock.pins16()
+    }
+
+    /// Returns pins for an 8-channel multiplexer: `(s0, s1, s2, en)`
+    pub fn pins8(&self) -> (MockPin, MockPin, MockPin, MockPin) {
+        self.mock.pins8()
+    }
+
+    /// Connects the given channel to `source`
+    pub fn set_source(&self, channel: u8, source: Source) {
+        self.state.borrow_mut().sources[channel as usize] = source;
+    }
+
+    /// Sets how long (in µs) every ADC conversion takes (default: 10)
+    pub fn set_conversion_time(&self, conversion_us: u32) {
+        self.state.borrow_mut().conversion_us = conversion_us;
+    }
+
+    /// Models the common pin as an RC circuit with the given time
+    /// constant (in µs) so readings taken too soon after switching
+    /// channels still contain some of the previous channel's value.
+    /// `None` (the default) means the output settles instantly.
+    pub fn set_settling(&self, tau_us: Option<f32>) {
+        self.state.borrow_mut().settling_tau_us = tau_us;
+    }
+
+    /// Mixes the given fraction (0.0-1.0) of the previously-read
+    /// channel's value into every reading of a different channel
+    pub fn set_crosstalk(&self, fraction: f32) {
+        self.state.borrow_mut().crosstalk = fraction;
+    }
+
+    /// Sets the value read while the multiplexer is disabled (default: 0)
+    pub fn set_floating(&self, value: u16) {
+        self.state.borrow_mut().floating = value;
+    }
+
+    /// Returns the simulator's clock (in µs)
+    pub fn now_us(&self) -> u64 {
+        self.state.borrow().now_us
+    }
+
+    /// Moves the simulator's clock forward
+    pub fn advance(&self, us: u32) {
+        self.state.borrow_mut().now_us += us as u64;
+    }
+
+    /// Returns an ADC that reads the simulated common pin
+    pub fn adc(&self) -> SimAdc {
+        SimAdc { sim: self.clone() }
+    }
+
+    /// Returns a delay provider that moves the simulator's clock forward
+    /// (instead of actually waiting)
+    pub fn delay(&self) -> SimDelay {
+        SimDelay { sim: self.clone() }
+    }
+
+    /// Returns every conversion performed so far (in order)
+    pub fn reads(&self) -> Vec<Read> {
+        self.state.borrow().reads.clone()
+    }
+
+    fn convert(&self) -> u16 {
+        let channel = self.mock.selected();
+        let seq = self.mock.seq();
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.now_us += state.conversion_us as u64;
+        let now_us = state.now_us;
+        let target = match channel {
+            Some(chan) => match state.sources.get_mut(chan as usize) {
+                Some(source) => source.value(now_us) as f32,
+                None => state.floating as f32,
+            },
+            None => state.floating as f32,
+        };
+        state.node = match state.settling_tau_us {
+            Some(tau_us) if tau_us > 0.0 => {
+                let elapsed = (now_us - state.node_us) as f32;
+                target + (state.node - target) * (-elapsed / tau_us).exp()
+            }
+            _ => target,
+        };
+        state.node_us = now_us;
+        let mut value = state.node;
+        if channel.is_some() && channel != state.last_channel && state.last_channel.is_some() {
+            value = value * (1.0 - state.crosstalk) + state.last_value * state.crosstalk;
+        }
+        state.last_channel = channel;
+        state.last_value = target;
+        let value = value.round().max(0.0).min(u16::MAX as f32) as u16;
+        state.reads.push(Read {
+            seq,
+            time_us: now_us,
+            channel,
+            value,
+        });
+        value
+    }
+}
+
+/// The simulated common (`Z`) pin, read with a `SimAdc`
+pub struct SimPin;
+
+impl Channel<SimAdc> for SimPin {
+    type ID = u8;
+
+    fn channel() -> u8 {
+        0
+    }
+}
+
+/// The simulated ADC (see `Simulator::adc()`)
+pub struct SimAdc {
+    sim: Simulator,
+}
+
+impl OneShot<SimAdc, u16, SimPin> for SimAdc {
+    type Error = Infallible;
+
+    fn read(&mut self, _pin: &mut SimPin) -> nb::Result<u16, Self::Error> {
+        Ok(self.sim.convert())
+    }
+}
+
+/// A `Delay` that moves the simulator's clock forward (see
+/// `Simulator::delay()`)
+pub struct SimDelay {
+    sim: Simulator,
+}
+
+impl Delay for SimDelay {
+    fn delay_us(&mut self, us: u32) {
+        self.sim.advance(us);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Multiplexer;
+
+    #[test]
+    fn scan_routes_selected_channel() {
+        let sim = Simulator::new(16);
+        for chan in 0..16 {
+            sim.set_source(chan, Source::Constant(chan as u16 * 100));
+        }
+        let mut multiplexer = Multiplexer::new(sim.pins16())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        let mut values = [0u16; 16];
+        multiplexer.read_all(&mut values).unwrap();
+        for (chan, value) in values.iter().enumerate() {
+            assert_eq!(*value, chan as u16 * 100);
+        }
+        for read in sim.reads() {
+            assert_eq!(read.value, read.channel.unwrap() as u16 * 100);
+        }
+    }
+
+    #[test]
+    fn scan_into_snapshot() {
+        let sim = Simulator::new(8);
+        sim.set_source(6, Source::Constant(600));
+        let mut multiplexer = Multiplexer::new(sim.pins8())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        let snapshot: crate::Snapshot<8> = multiplexer.scan().unwrap();
+        assert_eq!(snapshot[6], 600);
+        assert_eq!(snapshot.iter().filter(|(_, v)| *v != 0).count(), 1);
+    }
+
+    #[test]
+    fn gray_scan_changes_one_select_line_at_a_time() {
+        let sim = Simulator::new(16);
+        for chan in 0..16 {
+            sim.set_source(chan, Source::Constant(chan as u16 * 10));
+        }
+        let mut multiplexer = Multiplexer::new(sim.pins16())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        let seq = sim.mock().seq();
+        let snapshot: crate::Snapshot<16> =
+            multiplexer.scan_ordered(crate::ScanOrder::Gray).unwrap();
+        for (chan, value) in &snapshot {
+            assert_eq!(value, chan as u16 * 10); // Still stored by channel
+        }
+        assert_eq!(sim.mock().seq() - seq, 15);
+        let visited: Vec<_> = sim.reads().iter().map(|r| r.channel.unwrap()).collect();
+        assert_eq!(visited[..5], [0, 1, 3, 2, 6]);
+    }
+
+    #[test]
+    fn disabled_reads_floating() {
+        let sim = Simulator::new(8);
+        sim.set_source(0, Source::Constant(500));
+        sim.set_floating(42);
+        let mut multiplexer = Multiplexer::new(sim.pins8())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        multiplexer.multiplexer.disable().unwrap();
+        assert_eq!(multiplexer.read_channel(0).unwrap(), 42);
+        assert_eq!(sim.reads()[0].channel, None);
+    }
+
+    #[test]
+    fn settle_delay_beats_settling_lag() {
+        let sim = Simulator::new(8);
+        sim.set_source(1, Source::Constant(4000));
+        sim.set_settling(Some(5.0));
+        sim.set_conversion_time(1);
+        let mut multiplexer = Multiplexer::new(sim.pins8())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        // Without waiting the reading still has a lot of channel 0 (0) in it
+        assert!(multiplexer.read_channel(1).unwrap() < 1000);
+        multiplexer.read_channel(0).unwrap();
+        // Waiting 10 time constants after every switch gets the full value
+        let (mux, adc, pin) = multiplexer.release();
+        let mut multiplexer = mux.with_delay(sim.delay(), 50).with_adc(adc, pin);
+        assert_eq!(multiplexer.read_channel(1).unwrap(), 4000);
+    }
+
+    #[test]
+    fn crosstalk_from_previous_channel() {
+        let sim = Simulator::new(8);
+        sim.set_source(2, Source::Constant(1000));
+        sim.set_crosstalk(0.1);
+        let mut multiplexer = Multiplexer::new(sim.pins8())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        assert_eq!(multiplexer.read_channel(2).unwrap(), 1000);
+        assert_eq!(multiplexer.read_channel(3).unwrap(), 100);
+        assert_eq!(multiplexer.read_channel(3).unwrap(), 0);
+    }
+
+    #[test]
+    fn sources_follow_the_clock() {
+        let sim = Simulator::new(8);
+        sim.set_conversion_time(0);
+        sim.set_source(
+            0,
+            Source::Trace {
+                samples: vec![1, 2, 3],
+                interval_us: 10,
+            },
+        );
+        sim.set_source(1, Source::Func(Box::new(|now_us| now_us as u16)));
+        sim.set_source(
+            2,
+            Source::Sine {
+                offset: 2000,
+                amplitude: 1000,
+                period_us: 40,
+            },
+        );
+        let mut multiplexer = Multiplexer::new(sim.pins8())
+            .unwrap()
+            .with_adc(sim.adc(), SimPin);
+        assert_eq!(multiplexer.read_channel(0).unwrap(), 1);
+        sim.advance(25);
+        assert_eq!(multiplexer.read_channel(0).unwrap(), 3);
+        assert_eq!(multiplexer.read_channel(1).unwrap(), 25);
+        sim.advance(5); // 30µs is 3/4 of the way through the sine
+        assert_eq!(multiplexer.read_channel(2).unwrap(), 1000);
+    }
+}
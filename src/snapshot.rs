@@ -0,0 +1,172 @@
+//! A reading of every channel on a multiplexer (see
+//! `AnalogMultiplexer::scan()`).
+
+use core::fmt;
+#[cfg(feature = "eh0")]
+use core::marker::PhantomData;
+use core::ops::{Index, IndexMut};
+
+#[cfg(feature = "eh0")]
+use crate::Output;
+
+/// The value of every channel on an `N`-channel multiplexer, stored
+/// by channel (e.g. `Snapshot<16>` for a 74HC4067).  It can be indexed
+/// by channel (`snapshot[5]`), iterated as `(channel, value)` pairs,
+/// and pretty-printed as a table via `Display`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot<const N: usize> {
+    pub values: [u16; N],
+}
+
+impl<const N: usize> Snapshot<N> {
+    /// Returns a `Snapshot` with every channel set to 0
+    pub const fn new() -> Self {
+        Self { values: [0; N] }
+    }
+
+    /// Returns the number of channels in the snapshot
+    pub const fn len(&self) -> usize {
+        N
+    }
+
+    /// Returns `true` if the snapshot has no channels at all
+    pub const fn is_empty(&self) -> bool {
+        N == 0
+    }
+
+    /// Returns the value of the given channel (`None` if out of range)
+    pub fn get(&self, channel: u8) -> Option<u16> {
+        self.values.get(channel as usize).copied()
+    }
+
+    /// Returns an iterator over every `(channel, value)` pair
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            values: self.values.iter(),
+            channel: 0,
+        }
+    }
+}
+
+impl<const N: usize> Default for Snapshot<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> From<[u16; N]> for Snapshot<N> {
+    fn from(values: [u16; N]) -> Self {
+        Self { values }
+    }
+}
+
+impl<const N: usize> Index<u8> for Snapshot<N> {
+    type Output = u16;
+
+    fn index(&self, channel: u8) -> &u16 {
+        &self.values[channel as usize]
+    }
+}
+
+impl<const N: usize> IndexMut<u8> for Snapshot<N> {
+    fn index_mut(&mut self, channel: u8) -> &mut u16 {
+        &mut self.values[channel as usize]
+    }
+}
+
+/// An iterator over a `Snapshot`'s `(channel, value)` pairs
+pub struct Iter<'a> {
+    values: core::slice::Iter<'a, u16>,
+    channel: u8,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (u8, u16);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let value = *self.values.next()?;
+        let channel = self.channel;
+        self.channel = self.channel.wrapping_add(1);
+        Some((channel, value))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.values.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+impl<'a, const N: usize> IntoIterator for &'a Snapshot<N> {
+    type Item = (u8, u16);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Prints the channels as a table, 8 channels per row:
+///
+/// ```text
+/// ch0     ch1     ch2     ch3     ch4     ch5     ch6     ch7
+/// 2047    12      4095    0       1837    2048    3       980
+/// ch8     ch9     ...
+/// ```
+impl<const N: usize> fmt::Display for Snapshot<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (row, values) in self.values.chunks(8).enumerate() {
+            for i in 0..values.len() {
+                write!(f, "ch{}\t", row * 8 + i)?;
+            }
+            f.write_str("\n")?;
+            for value in values {
+                write!(f, "{}\t", value)?;
+            }
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compile-time check that a `Snapshot<N>` matches an `Output`
+#[cfg(feature = "eh0")]
+pub(crate) struct SnapshotSize<Pins, const N: usize>(PhantomData<Pins>);
+
+#[cfg(feature = "eh0")]
+impl<Pins: Output, const N: usize> SnapshotSize<Pins, N> {
+    pub(crate) const OK: () = assert!(
+        N == Pins::NUM_CHANNELS as usize && N != 0,
+        "Snapshot<N> doesn't match the multiplexer's number of channels"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+    use std::vec::Vec;
+
+    #[test]
+    fn index_and_iterate_by_channel() {
+        let mut snapshot = Snapshot::<4>::new();
+        snapshot[2] = 42;
+        assert_eq!(snapshot[2], 42);
+        assert_eq!(snapshot.get(2), Some(42));
+        assert_eq!(snapshot.get(4), None);
+        let pairs: Vec<_> = snapshot.iter().collect();
+        assert_eq!(pairs, [(0, 0), (1, 0), (2, 42), (3, 0)]);
+    }
+
+    #[test]
+    fn display_as_table() {
+        let snapshot = Snapshot::from([1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(
+            format!("{}", snapshot),
+            "ch0\tch1\tch2\tch3\tch4\tch5\tch6\tch7\t\n\
+             1\t2\t3\t4\t5\t6\t7\t8\t\n\
+             ch8\tch9\t\n\
+             9\t10\t\n"
+        );
+    }
+}
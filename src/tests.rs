@@ -0,0 +1,495 @@
+//! Host-side behavioural tests using the recording pins from `mock`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::vec::Vec;
+
+use crate::mock::{Line, MockError, MockMux};
+use crate::{
+    Channel, ChannelError, Delay, DummyPin, Error, Inverted, Multiplexer, NoDelay, NoEnable, Pin,
+    PortError, PortOutput, SelectSlice,
+};
+
+const L: bool = false;
+const H: bool = true;
+const S0: Line = Line::Select(0);
+const S1: Line = Line::Select(1);
+const S2: Line = Line::Select(2);
+const S3: Line = Line::Select(3);
+const EN: Line = Line::Enable;
+
+#[test]
+fn new_enables_then_selects_channel_0_16ch() {
+    let mock = MockMux::new();
+    let mux = Multiplexer::new(mock.pins16()).unwrap();
+    assert_eq!(
+        mock.take_levels(),
+        [(EN, L), (S0, L), (S1, L), (S2, L), (S3, L)]
+    );
+    assert_eq!(mux.num_channels, 16);
+    assert_eq!(mux.active_channel, 0);
+    assert!(mux.enabled);
+}
+
+#[test]
+fn new_enables_then_selects_channel_0_8ch() {
+    let mock = MockMux::new();
+    let mux = Multiplexer::new(mock.pins8()).unwrap();
+    assert_eq!(mock.take_levels(), [(EN, L), (S0, L), (S1, L), (S2, L)]);
+    assert_eq!(mux.num_channels, 8);
+    assert_eq!(mux.active_channel, 0);
+    assert!(mux.enabled);
+}
+
+#[test]
+fn set_channel_drives_select_pins_16ch() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins16()).unwrap();
+    mock.take_levels();
+    mux.set_channel(5).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, H), (S2, H)]); // Only the pins that change
+    assert_eq!(mux.active_channel, 5);
+    mux.set_channel(10).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, L), (S1, H), (S2, L), (S3, H)]);
+    assert_eq!(mux.active_channel, 10);
+    mux.set_channel(15).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, H), (S2, H)]);
+    assert_eq!(mux.active_channel, 15);
+}
+
+#[test]
+fn set_channel_drives_select_pins_8ch() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins8()).unwrap();
+    mock.take_levels();
+    mux.set_channel(6).unwrap();
+    assert_eq!(mock.take_levels(), [(S1, H), (S2, H)]); // Only the pins that change
+    assert_eq!(mux.active_channel, 6);
+    mux.set_channel(1).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, H), (S1, L), (S2, L)]);
+    assert_eq!(mux.active_channel, 1);
+}
+
+#[test]
+fn enable_and_disable_drive_en_16ch() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins16()).unwrap();
+    mock.take_levels();
+    mux.disable().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, H)]);
+    assert!(!mux.enabled);
+    mux.enable().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, L)]);
+    assert!(mux.enabled);
+}
+
+#[test]
+fn enable_and_disable_drive_en_8ch() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins8()).unwrap();
+    mock.take_levels();
+    mux.disable().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, H)]);
+    assert!(!mux.enabled);
+    mux.enable().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, L)]);
+    assert!(mux.enabled);
+}
+
+#[test]
+fn failing_pin_is_reported_and_state_is_kept() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins16()).unwrap();
+    mux.set_channel(3).unwrap();
+    mock.set_failing(S2, true);
+    assert_eq!(mux.set_channel(4), Err(Error::S2(MockError(S2))));
+    assert_eq!(mux.active_channel, 3);
+}
+
+#[test]
+fn failed_switch_drives_every_pin_next_time() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins16()).unwrap();
+    mock.set_failing(S2, true);
+    assert_eq!(mux.set_channel(5), Err(Error::S2(MockError(S2))));
+    mock.set_failing(S2, false);
+    mock.take_levels();
+    // S0 was already set high but that can't be relied upon anymore
+    mux.set_channel(5).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, H), (S1, L), (S2, H), (S3, L)]);
+    mux.set_channel(5).unwrap();
+    assert_eq!(mock.take_levels(), []);
+}
+
+#[test]
+fn failed_switch_settles_next_time() {
+    let mock = MockMux::new();
+    let waits = Rc::new(RefCell::new(Vec::new()));
+    let mut mux = Multiplexer::new(mock.pins8())
+        .unwrap()
+        .with_delay(RecordingDelay(waits.clone()), 5);
+    mux.set_channel(3).unwrap();
+    mock.set_failing(S2, true);
+    // S0 and S1 get set low before S2 fails (so channel 0 is connected)
+    assert_eq!(mux.set_channel(4), Err(Error::S2(MockError(S2))));
+    assert_eq!(mock.selected(), Some(0));
+    mock.set_failing(S2, false);
+    // Still channel 3 as far as `mux` knows but it has to settle again
+    mux.set_channel(3).unwrap();
+    assert_eq!(mock.selected(), Some(3));
+    assert_eq!(*waits.borrow(), [5, 5]);
+    mux.set_channel(3).unwrap();
+    assert_eq!(*waits.borrow(), [5, 5]);
+}
+
+#[test]
+fn resync_drives_every_pin() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins8()).unwrap();
+    mux.set_channel(6).unwrap();
+    mock.take_levels();
+    mux.set_channel(6).unwrap();
+    assert_eq!(mock.take_levels(), []); // Nothing changed so nothing's written
+    mux.resync().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, L), (S0, L), (S1, H), (S2, H)]);
+    mux.disable().unwrap();
+    mock.take_levels();
+    mux.resync().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, H), (S0, L), (S1, H), (S2, H)]);
+}
+
+#[test]
+fn failing_en_pin_is_reported_and_state_is_kept() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins8()).unwrap();
+    mock.set_failing(EN, true);
+    assert_eq!(mux.disable(), Err(Error::EN(MockError(EN))));
+    assert!(mux.enabled);
+}
+
+#[test]
+fn mock_tracks_electrically_selected_channel() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins16()).unwrap();
+    assert_eq!(mock.selected(), Some(0));
+    mux.set_channel(7).unwrap();
+    let seq = mock.seq();
+    mux.set_channel(8).unwrap();
+    assert_eq!(mock.selected_at(seq), Some(7));
+    // S0 was set first so channel 6 was briefly selected on the way to 8
+    assert_eq!(mock.selected_at(seq + 1), Some(6));
+    assert_eq!(mock.selected(), Some(8));
+    mux.disable().unwrap();
+    assert_eq!(mock.selected(), None);
+    assert_eq!(mock.level(EN), Some(H));
+}
+
+#[test]
+fn mock_remembers_levels_across_clear() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins16()).unwrap();
+    mux.set_channel(4).unwrap();
+    let seq = mock.seq();
+    mock.clear();
+    assert_eq!(mock.selected(), Some(4));
+    mux.set_channel(5).unwrap(); // Only S0 gets written
+    assert_eq!(mock.selected_at(seq), Some(4));
+    assert_eq!(mock.take_levels(), [(S0, H)]);
+    assert_eq!(mock.selected(), Some(5));
+    assert_eq!(mock.level(S2), Some(H));
+}
+
+#[test]
+fn try_set_channel_rejects_out_of_range() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins8()).unwrap();
+    mock.take_levels();
+    assert_eq!(mux.try_set_channel(8), Err(ChannelError::InvalidChannel(8)));
+    assert_eq!(mock.take_levels(), []);
+    assert_eq!(mux.active_channel, 0);
+    mux.try_set_channel(7).unwrap();
+    assert_eq!(mux.active_channel, 7);
+}
+
+#[test]
+fn select_typed_channel() {
+    let mut mux = Multiplexer::new((DummyPin, DummyPin, DummyPin, DummyPin, DummyPin)).unwrap();
+    for chan in Channel::<16>::all() {
+        mux.select(chan).unwrap();
+        assert_eq!(mux.active_channel, chan.index());
+    }
+    assert_eq!(Channel::<16>::new(16), None);
+}
+
+/// Records how long it was asked to wait
+struct RecordingDelay(Rc<RefCell<Vec<u32>>>);
+
+impl Delay for RecordingDelay {
+    fn delay_us(&mut self, us: u32) {
+        self.0.borrow_mut().push(us);
+    }
+}
+
+#[test]
+fn settle_delay_after_switching() {
+    let waits = Rc::new(RefCell::new(Vec::new()));
+    let mut mux = Multiplexer::new((DummyPin, DummyPin, DummyPin, DummyPin))
+        .unwrap()
+        .with_delay(RecordingDelay(waits.clone()), 5)
+        .with_settle_times([None; 8]);
+    mux.set_settle_time(3, Some(50)).unwrap();
+    mux.set_channel(1).unwrap();
+    mux.set_channel(1).unwrap(); // No switch so no waiting
+    mux.set_channel(3).unwrap();
+    mux.disable().unwrap();
+    mux.enable().unwrap();
+    assert_eq!(*waits.borrow(), [5, 50, 50]);
+}
+
+#[test]
+fn settle_time_overrides_are_checked() {
+    let mut mux = Multiplexer::new((DummyPin, DummyPin, DummyPin, DummyPin))
+        .unwrap()
+        .with_settle_times([None; 4]);
+    assert_eq!(mux.set_settle_time(3, Some(50)), Ok(()));
+    assert_eq!(mux.settle_time(3), 50);
+    // Beyond the table
+    assert_eq!(
+        mux.set_settle_time(4, Some(50)),
+        Err(ChannelError::InvalidChannel(4))
+    );
+    assert_eq!(mux.set_settle_time(4, None), Ok(()));
+    // Beyond the multiplexer
+    assert_eq!(
+        mux.set_settle_time(8, None),
+        Err(ChannelError::InvalidChannel(8))
+    );
+    // Without a table there's nothing to override
+    let mut mux = Multiplexer::new((DummyPin, DummyPin, DummyPin, DummyPin)).unwrap();
+    assert_eq!(
+        mux.set_settle_time(0, Some(50)),
+        Err(ChannelError::InvalidChannel(0))
+    );
+    assert_eq!(mux.settle_time(0), 0);
+}
+
+#[test]
+fn break_before_make_disables_while_switching() {
+    let mock = MockMux::new();
+    let waits = Rc::new(RefCell::new(Vec::new()));
+    let mut mux = Multiplexer::new(mock.pins8())
+        .unwrap()
+        .with_delay(RecordingDelay(waits.clone()), 0);
+    mux.set_break_before_make_guard(2);
+    mock.take_levels();
+    mux.set_channel(3).unwrap();
+    assert_eq!(mock.take_levels(), [(EN, H), (S0, H), (S1, H), (EN, L)]);
+    assert_eq!(*waits.borrow(), [2]);
+    mux.set_channel(3).unwrap(); // Nothing changes so EN is left alone
+    assert_eq!(mock.take_levels(), []);
+    // Never selects an intermediate channel
+    let seq = mock.seq();
+    mux.set_channel(4).unwrap();
+    assert!((seq + 1..mock.seq()).all(|s| mock.selected_at(s).is_none()));
+    assert_eq!(mock.selected(), Some(4));
+    assert!(mux.enabled);
+    // ...or enables a disabled multiplexer
+    mux.disable().unwrap();
+    mock.take_levels();
+    mux.set_channel(5).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, H)]);
+    assert!(!mux.enabled);
+}
+
+#[test]
+fn break_before_make_is_skipped_without_en_pin() {
+    let waits = Rc::new(RefCell::new(Vec::new()));
+    let mut mux = Multiplexer::new((DummyPin, DummyPin, DummyPin, NoEnable))
+        .unwrap()
+        .with_delay(RecordingDelay(waits.clone()), 0);
+    mux.set_break_before_make_guard(2);
+    mux.set_channel(3).unwrap();
+    assert!(waits.borrow().is_empty());
+}
+
+#[test]
+fn break_before_make_is_not_skipped_for_dummy_pin() {
+    // `DummyPin` looks like any other pin so the guard time is waited out
+    let waits = Rc::new(RefCell::new(Vec::new()));
+    let mut mux = Multiplexer::new((DummyPin, DummyPin, DummyPin, DummyPin))
+        .unwrap()
+        .with_delay(RecordingDelay(waits.clone()), 0);
+    mux.set_break_before_make_guard(2);
+    mux.set_channel(3).unwrap();
+    assert_eq!(*waits.borrow(), [2]);
+}
+
+#[test]
+fn break_before_make_without_a_delay() {
+    let mock = MockMux::new();
+    let mut mux = Multiplexer::new(mock.pins8()).unwrap();
+    mux.set_break_before_make(true);
+    assert_eq!(mux.break_before_make(), Some(0));
+    mock.take_levels();
+    mux.set_channel(3).unwrap();
+    assert_eq!(mock.take_levels(), [(EN, H), (S0, H), (S1, H), (EN, L)]);
+    // A guard time set with a delay is dropped along with the delay
+    let waits = Rc::new(RefCell::new(Vec::new()));
+    let mut mux = mux.with_delay(RecordingDelay(waits.clone()), 0);
+    mux.set_break_before_make_guard(2);
+    let mux = mux.with_delay(NoDelay, 0);
+    assert_eq!(mux.break_before_make(), Some(0));
+}
+
+#[test]
+fn inverted_en_and_select_pins() {
+    let mock = MockMux::new();
+    let (s0, s1, s2, en) = mock.pins8();
+    let mut mux = Multiplexer::new((s0, Inverted(s1), s2, Inverted(en))).unwrap();
+    // Active-high EN and S1 inverted right from the start
+    assert_eq!(mock.take_levels(), [(EN, H), (S0, L), (S1, H), (S2, L)]);
+    mux.set_channel(3).unwrap();
+    assert_eq!(mock.take_levels(), [(S0, H), (S1, L)]);
+    mux.disable().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, L)]);
+    mux.resync().unwrap();
+    assert_eq!(mock.take_levels(), [(EN, L), (S0, H), (S1, L), (S2, L)]);
+}
+
+#[test]
+fn num_channels_follows_number_of_select_pins() {
+    let mock = MockMux::new();
+    let pin = |n| mock.pin(Line::Select(n));
+    let mux = Multiplexer::new((pin(0), mock.pin(EN))).unwrap();
+    assert_eq!(mux.num_channels, 2);
+    let mux = Multiplexer::new((pin(0), pin(1), mock.pin(EN))).unwrap();
+    assert_eq!(mux.num_channels, 4);
+    let mux = Multiplexer::new((pin(0), pin(1), pin(2), pin(3), pin(4), mock.pin(EN))).unwrap();
+    assert_eq!(mux.num_channels, 32);
+}
+
+#[test]
+fn set_channel_drives_select_pins_32ch() {
+    let mock = MockMux::new();
+    let pin = |n| mock.pin(Line::Select(n));
+    let mut mux = Multiplexer::new((pin(0), pin(1), pin(2), pin(3), pin(4), mock.pin(EN))).unwrap();
+    for chan in 0..32 {
+        mux.set_channel(chan).unwrap();
+        assert_eq!(mock.selected(), Some(chan));
+    }
+    mock.set_failing(Line::Select(4), true);
+    assert_eq!(
+        mux.set_channel(0),
+        Err(Error::S4(MockError(Line::Select(4))))
+    );
+}
+
+#[test]
+fn select_pins_without_en_pin() {
+    let mock = MockMux::new();
+    let pins = (mock.pin(S0), mock.pin(S1));
+    let mut mux = Multiplexer::new(PortOutput::without_enable(pins)).unwrap();
+    assert_eq!(mux.num_channels, 4);
+    mux.set_channel(2).unwrap();
+    assert_eq!(mock.selected(), Some(2)); // No EN pin means always enabled
+}
+
+#[test]
+fn new_hands_the_pins_back_on_failure() {
+    let mock = MockMux::new();
+    mock.set_failing(S1, true);
+    let err = Multiplexer::new(mock.pins8()).err().unwrap();
+    assert_eq!(err.error, Error::S1(MockError(S1)));
+    // E.g. once the I/O expander has been reset
+    mock.set_failing(S1, false);
+    let mux = Multiplexer::new(err.pins).unwrap();
+    assert_eq!(mux.active_channel, 0);
+    assert_eq!(mock.selected(), Some(0));
+}
+
+#[test]
+fn array_of_select_pins() {
+    let mock = MockMux::new();
+    let pins = [0, 1, 2, 3].map(|n| mock.pin(Line::Select(n)));
+    let mut mux = Multiplexer::new(PortOutput::new(pins, mock.pin(EN))).unwrap();
+    assert_eq!(mux.num_channels, 16);
+    mock.take_levels();
+    mux.set_channel(6).unwrap();
+    assert_eq!(mock.take_levels(), [(S1, H), (S2, H)]);
+    mock.set_failing(S3, true);
+    assert_eq!(
+        mux.set_channel(8),
+        Err(PortError::Select(Error::S3(MockError(S3))))
+    );
+}
+
+#[test]
+fn slice_of_trait_objects() {
+    use eh1::digital::OutputPin;
+
+    let mock = MockMux::new();
+    let (mut s0, mut s1, mut s2, en) = mock.pins8();
+    // E.g. picked at runtime from the board revision
+    let mut pins: [&mut dyn OutputPin<Error = MockError>; 3] = [&mut s0, &mut s1, &mut s2];
+    let select = SelectSlice::new(&mut pins[..2]).unwrap();
+    let mut mux = Multiplexer::new(PortOutput::new(select, en)).unwrap();
+    assert_eq!(mux.num_channels, 4);
+    for chan in 0..4 {
+        mux.set_channel(chan).unwrap();
+        assert_eq!(mock.level(S0), Some(chan & 1 != 0));
+        assert_eq!(mock.level(S1), Some(chan & 2 != 0));
+    }
+    assert_eq!(mux.try_set_channel(4), Err(ChannelError::InvalidChannel(4)));
+    // S2 was never touched
+    assert_eq!(mock.level(S2), None);
+}
+
+#[test]
+fn select_slices_need_1_to_5_pins() {
+    let mut pins = [NoEnable, NoEnable, NoEnable, NoEnable, NoEnable, NoEnable];
+    assert!(SelectSlice::new(&mut pins[..0]).is_none());
+    assert!(SelectSlice::new(&mut pins[..]).is_none());
+    let select = SelectSlice::new(&mut pins[..5]).unwrap();
+    let mux = Multiplexer::new(PortOutput::without_enable(select)).unwrap();
+    assert_eq!(mux.num_channels, 32);
+}
+
+#[derive(Debug, PartialEq)]
+struct I2cError;
+
+/// A pin on an I/O expander (with its own error type)
+struct ExpanderPin {
+    failing: bool,
+}
+
+impl Pin for ExpanderPin {
+    type Error = I2cError;
+
+    fn set_low(&mut self) -> Result<(), I2cError> {
+        if self.failing {
+            Err(I2cError)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn set_high(&mut self) -> Result<(), I2cError> {
+        self.set_low()
+    }
+}
+
+#[test]
+fn pins_with_different_error_types() {
+    let mock = MockMux::new();
+    let (s0, s1, s2, _) = mock.pins8();
+    let s3 = ExpanderPin { failing: false };
+    let mut mux = Multiplexer::new((s0, s1, s2, s3, DummyPin)).unwrap();
+    mux.set_channel(9).unwrap();
+    assert_eq!(mock.level(S0), Some(H));
+    assert_eq!(mock.level(S1), Some(L));
+    mux.pins.3.failing = true;
+    assert_eq!(mux.set_channel(1), Err(Error::S3(I2cError)));
+    mux.pins.3.failing = false;
+    mock.set_failing(S1, true);
+    assert_eq!(mux.set_channel(3), Err(Error::S1(MockError(S1))));
+}